@@ -0,0 +1,53 @@
+//! Duplicate scan over a synthetic tree where most files share a size but not their
+//! contents, the case the size and quick-hash stages exist for. Prints the time per scan
+//! and what each stage read.
+
+use organizer::{DedupeMethod, DedupePlan};
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+use tempfile::TempDir;
+
+/// Files of one size with different contents; every tenth is a copy of the one before it.
+const SAME_SIZE: usize = 4000;
+const SIZE: usize = 200 * 1024;
+/// Files that each have a size of their own and so are never read.
+const UNIQUE_SIZES: usize = 4000;
+const RUNS: u32 = 5;
+
+fn build(root: &Path) {
+    let mut data = vec![0u8; SIZE];
+    for i in 0..SAME_SIZE {
+        let dir = root.join(format!("same/{}", i / 100));
+        fs::create_dir_all(&dir).unwrap();
+        // Differ in the middle only, past what the quick hash reads
+        if i % 10 != 9 {
+            data[SIZE / 2..SIZE / 2 + 8].copy_from_slice(&(i as u64).to_le_bytes());
+        }
+        fs::write(dir.join(format!("{i}.bin")), &data).unwrap();
+    }
+    for i in 0..UNIQUE_SIZES {
+        let dir = root.join(format!("unique/{}", i / 100));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(format!("{i}.bin")), vec![1u8; i + 1]).unwrap();
+    }
+}
+
+fn main() {
+    let tmp = TempDir::new().unwrap();
+    build(tmp.path());
+    for full_hash in [false, true] {
+        let started = Instant::now();
+        let mut groups = 0;
+        let mut stats = Default::default();
+        for _ in 0..RUNS {
+            let mut plan = DedupePlan::new(vec![DedupeMethod::Hash]);
+            plan.set_full_hash(full_hash);
+            plan.scan(tmp.path()).unwrap();
+            groups = plan.report().len();
+            stats = plan.stats();
+        }
+        println!("dedupe hash{}: {:?} per scan, {} groups, {:?}",
+            if full_hash { " (full)" } else { "" }, started.elapsed() / RUNS, groups, stats);
+    }
+}
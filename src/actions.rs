@@ -1,20 +1,172 @@
+use crate::quarantine::Quarantine;
+use crate::utils::{copy_file, file_id, has_other_links, hash_file, is_pattern_match, make_symlink, normalize_file_name, open_files, path_size, same_location, NameNormalization, Throttle};
+use std::collections::{HashMap, HashSet};
 use anyhow::{Context, Result};
+use globset::GlobSet;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
 use std::fs;
 use std::io;
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Action {
     MoveFile(PathBuf, PathBuf), // src, dest_dir
     MoveDir(PathBuf, PathBuf),  // src_dir, dest_dir
     Delete(PathBuf, String),    // path, reason
 }
 
+impl Action {
+    /// Whether it would leave everything where it is: the file or directory already sits
+    /// at its destination, as on a re-run over an organized tree.
+    pub fn is_in_place(&self, normalize: Option<NameNormalization>) -> bool {
+        match self {
+            Action::MoveFile(src, dest_dir) => same_location(src, &file_dest(src, dest_dir, normalize)),
+            Action::MoveDir(src, dest) => same_location(src, dest),
+            Action::Delete(..) => false,
+        }
+    }
+}
+
+/// What a whole-directory move does when its destination already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirConflict {
+    /// Move it next to the existing one as `name-1`
+    Suffix,
+    /// Move its contents into the existing directory, file by file
+    Merge,
+    /// Leave it where it is
+    Skip,
+}
+
+/// What a whole-directory move does when the directory already at its destination holds
+/// exactly the same files (names and contents). Checked before `DirConflict`. In every
+/// mode a copy holding a `--dedup-protect` match outranks the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupeDirMode {
+    /// Not checked: `DirConflict` decides as for any other directory
+    Off,
+    /// The copy already at the destination stays; the incoming one is deleted
+    KeepExisting,
+    /// The survivor is picked like a duplicate file's: the copy under a preferred prefix
+    /// (`--dedup-keep-prefix`), else the path that sorts first
+    KeepPreferred,
+}
+
+/// Which copy of two identical directories survives, by the rules for duplicate files.
+#[derive(Debug, Clone, Default)]
+struct DirSurvivor {
+    /// Absolute prefixes whose copy survives under `DedupeDirMode::KeepPreferred`
+    preferred: Vec<PathBuf>,
+    /// `--dedup-protect`: a directory holding a match is never the one deleted
+    protect: Option<GlobSet>,
+    /// Trees that relative protect patterns are matched below
+    roots: Vec<PathBuf>,
+}
+
+impl DirSurvivor {
+    /// Whether `a` survives over `b`: the one under a preferred prefix, else the one that
+    /// sorts first, as for duplicate files.
+    fn prefers(&self, a: &Path, b: &Path) -> bool {
+        let preferred = |p: &Path| self.preferred.iter().any(|pre| p.starts_with(pre));
+        match (preferred(a), preferred(b)) {
+            (true, false) => true,
+            (false, true) => false,
+            _ => a < b,
+        }
+    }
+
+    /// Whether `dir` or anything below it matches a protect pattern.
+    fn is_protected(&self, dir: &Path) -> bool {
+        let Some(protect) = &self.protect else { return false };
+        let root = self.roots.iter().filter(|r| dir.starts_with(r)).max_by_key(|r| r.components().count());
+        walkdir::WalkDir::new(dir).into_iter().flatten().any(|e| {
+            protect.is_match(e.path()) || root.and_then(|r| e.path().strip_prefix(r).ok()).is_some_and(|rel| protect.is_match(rel))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Jsonl,
+}
+
+/// One line of the structured (JSONL) log file.
+#[derive(Serialize)]
+struct LogRecord<'a> {
+    op: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    src: Option<&'a Path>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dest: Option<&'a Path>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+    applied: bool,
+    timestamp: String,
+}
+
+/// Set by `request_stop`; engines check it between actions.
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/// Ask every engine to stop once the action in progress is done (the CLI's Ctrl-C handler).
+pub fn request_stop() {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+pub fn stop_requested() -> bool {
+    STOP.load(Ordering::SeqCst)
+}
+
 pub struct ActionEngine {
     apply: bool,
     allow_cross_device: bool,
     log_file: Option<std::fs::File>,
+    simulate: Option<(PathBuf, PathBuf)>, // src_root, shadow_root
+    freed_bytes: u64,
+    quiet: bool,
+    log_format: LogFormat,
+    fsync: bool,
+    open_files: Option<Arc<HashSet<PathBuf>>>,
+    normalize: Option<NameNormalization>,
+    errors: usize,
+    cross_device_skipped: usize,
+    throttle: Option<Arc<Throttle>>,
+    verify_copies: bool,
+    copy: bool,
+    op_interval: Option<Duration>,
+    last_op: Option<Instant>,
+    protect_marker: Option<String>,
+    jobs: usize,
+    dir_conflict: DirConflict,
+    dedupe_dirs: DedupeDirMode,
+    survivor: DirSurvivor,
+    /// Set on parallel workers: log lines wait here until the worker's group is done
+    buffer: Option<Vec<BufferedLine>>,
+    /// Dry run: destinations already handed out, so collisions show as they would happen
+    claimed: HashSet<PathBuf>,
+    /// With `set_record_placements`: where each moved or copied file and directory ended up
+    placed: Option<Vec<PathBuf>>,
+    /// First copy of each hardlinked source, by source (device, inode): its other links are
+    /// linked to that copy instead of being copied again. Shared with parallel workers.
+    link_copies: Arc<Mutex<HashMap<(u64, u64), PathBuf>>>,
+    /// The action `execute` is running, so its ERROR lines can be pinned on it
+    current: Option<Action>,
+    /// Every ERROR logged while running an action, with the message minus its `ERROR ` prefix
+    failures: Vec<(Action, String)>,
+}
+
+/// A log line held back by a parallel worker, with its JSONL form when that is the log format.
+struct BufferedLine {
+    line: String,
+    json: Option<String>,
 }
 
 impl ActionEngine {
@@ -22,27 +174,269 @@ impl ActionEngine {
         let log_file = if let Some(p) = log_path {
             Some(std::fs::OpenOptions::new().create(true).append(true).open(p)?)
         } else { None };
-        Ok(Self { apply, allow_cross_device, log_file })
+        Ok(Self { apply, allow_cross_device, log_file, simulate: None, freed_bytes: 0, quiet: false, log_format: LogFormat::Text, fsync: false, open_files: None, normalize: None, errors: 0, cross_device_skipped: 0, throttle: None, verify_copies: false, copy: false, op_interval: None, last_op: None, protect_marker: None, jobs: 1, dir_conflict: DirConflict::Suffix, dedupe_dirs: DedupeDirMode::Off, survivor: DirSurvivor::default(), buffer: None, claimed: HashSet::new(), placed: None, link_copies: Arc::default(), current: None, failures: Vec::new() })
+    }
+
+    /// Mirror every move as a placeholder under `shadow_root` instead of touching the real tree.
+    /// Destinations are re-rooted from `src_root` to `shadow_root`.
+    pub fn set_simulate(&mut self, src_root: &Path, shadow_root: PathBuf) {
+        self.apply = false;
+        self.simulate = Some((src_root.to_path_buf(), shadow_root));
     }
 
     pub fn apply_mode(&self) -> bool { self.apply }
 
-    pub fn execute_all(&mut self, actions: &[Action]) -> Result<()> {
+    pub fn quiet_mode(&self) -> bool { self.quiet }
+
+    /// Flush cross-device copies (file and parent dir) to disk before removing the source.
+    pub fn set_fsync(&mut self, fsync: bool) {
+        self.fsync = fsync;
+    }
+
+    /// Snapshot the files other processes hold open and skip moving them. Only Linux exposes
+    /// this (via `/proc`); elsewhere the snapshot is empty and nothing is skipped.
+    pub fn set_skip_open_files(&mut self, skip: bool) {
+        self.open_files = skip.then(|| Arc::new(open_files()));
+    }
+
+    /// Leave directories holding a file named `marker` (and everything below) out of pruning.
+    pub fn set_protect_marker(&mut self, marker: &str) {
+        self.protect_marker = (!marker.is_empty()).then(|| marker.to_string());
+    }
+
+    /// Clean up file names as they are moved (see `utils::normalize_file_name`).
+    pub fn set_normalize_names(&mut self, normalize: Option<NameNormalization>) {
+        self.normalize = normalize;
+    }
+
+    /// Compare blake3 hashes of source and copy before removing the source of a cross-device move.
+    pub fn set_verify_copies(&mut self, verify: bool) {
+        self.verify_copies = verify;
+    }
+
+    /// Copy instead of moving, leaving every source in place (mirror mode).
+    pub fn set_copy(&mut self, copy: bool) {
+        self.copy = copy;
+    }
+
+    /// Rate-limit cross-device copies to `bytes_per_sec`.
+    pub fn set_throttle(&mut self, bytes_per_sec: Option<u64>) {
+        self.throttle = bytes_per_sec.map(|b| Arc::new(Throttle::new(b)));
+    }
+
+    /// Space filesystem operations at most `per_sec` per second (0 = unlimited), for network
+    /// mounts that time out under a burst of renames.
+    pub fn set_max_ops_per_sec(&mut self, per_sec: u32) {
+        self.op_interval = (per_sec > 0).then(|| Duration::from_secs(1) / per_sec);
+    }
+
+    /// Run independent moves and deletes on up to `jobs` threads (1 = strictly in order,
+    /// 0 = one per CPU).
+    pub fn set_jobs(&mut self, jobs: usize) {
+        self.jobs = match jobs {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+    }
+
+    /// How `MoveDir` handles a destination directory that already exists.
+    pub fn set_dir_conflict(&mut self, policy: DirConflict) {
+        self.dir_conflict = policy;
+    }
+
+    /// Drop one of two identical directories when a `MoveDir` lands on the other; with
+    /// `KeepPreferred`, a copy under one of the absolute `preferred` prefixes survives.
+    pub fn set_dedupe_dirs(&mut self, mode: DedupeDirMode, preferred: Vec<PathBuf>) {
+        self.dedupe_dirs = mode;
+        self.survivor.preferred = preferred;
+    }
+
+    /// Never delete an identical directory holding a file or directory that matches
+    /// `protect` (by full path, or by the path below the deepest of `roots`): in any mode,
+    /// a protected copy survives, and two protected copies are left to `DirConflict`.
+    pub fn set_dedupe_protect(&mut self, protect: Option<GlobSet>, roots: Vec<PathBuf>) {
+        self.survivor.protect = protect;
+        self.survivor.roots = roots;
+    }
+
+    /// Remember where moves and copies put things (applied ones only), for `take_placements`.
+    pub fn set_record_placements(&mut self, on: bool) {
+        self.placed = on.then(Vec::new);
+    }
+
+    /// Final paths of everything moved or copied since the last call (renames included).
+    pub fn take_placements(&mut self) -> Vec<PathBuf> {
+        self.placed.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Format of the `--log-file` output; stdout always stays human-readable.
+    pub fn set_log_format(&mut self, format: LogFormat) {
+        self.log_format = format;
+    }
+
+    /// Keep per-action lines out of stdout (errors still print); the log file gets everything.
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    /// Number of actions that failed (each logged as an ERROR line).
+    pub fn error_count(&self) -> usize { self.errors }
+
+    /// The errors logged while executing actions, each with the action that hit it, in
+    /// order. Errors outside an action (flattening, dedupe links) are only counted.
+    pub fn failures(&self) -> &[(Action, String)] { &self.failures }
+
+    /// Moves skipped because they would cross filesystems without `allow_cross_device`.
+    pub fn cross_device_skips(&self) -> usize { self.cross_device_skipped }
+
+    /// Bytes released by deletions so far (would-be freed in dry-run).
+    pub fn freed_bytes(&self) -> u64 { self.freed_bytes }
+
+    /// Execute deletions largest-first until `target` bytes are freed. Other actions are skipped.
+    pub fn execute_deletes_until(&mut self, actions: &[Action], target: u64) -> Result<usize> {
+        let mut deletes: Vec<(u64, &Action)> = actions.iter()
+            .filter_map(|a| match a {
+                Action::Delete(p, _) => Some((path_size(p), a)),
+                _ => None,
+            })
+            .collect();
+        deletes.sort_by_key(|(size, _)| std::cmp::Reverse(*size));
+        let mut executed = 0;
+        for (_, a) in deletes {
+            if self.freed_bytes >= target || stop_requested() { break; }
+            self.execute(a)?;
+            executed += 1;
+        }
+        Ok(executed)
+    }
+
+    /// Run every action; returns those that ran, which is all of them unless a stop was
+    /// requested midway.
+    pub fn execute_all<'a>(&mut self, actions: &'a [Action]) -> Result<Vec<&'a Action>> {
+        // Dry runs are cheap, and pacing only holds for one thread at a time
+        if self.jobs > 1 && self.apply && self.op_interval.is_none() {
+            return self.execute_parallel(actions);
+        }
+        let mut done = Vec::with_capacity(actions.len());
         for a in actions {
+            if stop_requested() { break; }
             self.execute(a)?;
+            done.push(a);
+        }
+        Ok(done)
+    }
+
+    /// Moves sharing a destination directory run in order on one worker, so collision
+    /// suffixes stay race-free; everything else runs concurrently. Each worker's log lines
+    /// are flushed as one block when its group finishes.
+    fn execute_parallel<'a>(&mut self, actions: &'a [Action]) -> Result<Vec<&'a Action>> {
+        let groups = independent_groups(actions);
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(self.jobs).build()?;
+        let template = self.worker();
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::scope(|s| {
+            let (groups, template, pool) = (&groups, &template, &pool);
+            s.spawn(move || pool.install(|| groups.par_iter().for_each_with(tx, |tx, group| {
+                let mut worker = template.worker();
+                let mut done = Vec::new();
+                let res = group.iter().take_while(|_| !stop_requested()).try_for_each(|a| {
+                    worker.execute(a)?;
+                    done.push(*a);
+                    Ok(())
+                });
+                let _ = tx.send((worker, done, res));
+            })));
+            let mut first_err = None;
+            let mut all_done = Vec::with_capacity(actions.len());
+            for (worker, done, res) in rx {
+                self.merge(worker);
+                all_done.extend(done);
+                if let Err(err) = res {
+                    first_err.get_or_insert(err);
+                }
+            }
+            first_err.map_or(Ok(all_done), Err)
+        })
+    }
+
+    /// A copy of this engine's settings that buffers its log and starts from zero counts.
+    fn worker(&self) -> ActionEngine {
+        ActionEngine {
+            apply: self.apply,
+            allow_cross_device: self.allow_cross_device,
+            log_file: None,
+            simulate: self.simulate.clone(),
+            freed_bytes: 0,
+            quiet: self.quiet,
+            log_format: self.log_format,
+            fsync: self.fsync,
+            open_files: self.open_files.clone(),
+            normalize: self.normalize,
+            errors: 0,
+            cross_device_skipped: 0,
+            throttle: self.throttle.clone(),
+            verify_copies: self.verify_copies,
+            copy: self.copy,
+            op_interval: None,
+            last_op: None,
+            protect_marker: self.protect_marker.clone(),
+            jobs: 1,
+            dir_conflict: self.dir_conflict,
+            dedupe_dirs: self.dedupe_dirs,
+            survivor: self.survivor.clone(),
+            buffer: Some(Vec::new()),
+            claimed: HashSet::new(),
+            placed: self.placed.as_ref().map(|_| Vec::new()),
+            link_copies: self.link_copies.clone(),
+            current: None,
+            failures: Vec::new(),
+        }
+    }
+
+    fn merge(&mut self, worker: ActionEngine) {
+        self.errors += worker.errors;
+        self.failures.extend(worker.failures);
+        self.freed_bytes += worker.freed_bytes;
+        self.cross_device_skipped += worker.cross_device_skipped;
+        if let (Some(placed), Some(theirs)) = (self.placed.as_mut(), worker.placed) {
+            placed.extend(theirs);
+        }
+        for b in worker.buffer.unwrap_or_default() {
+            self.emit(&b.line, b.json.as_deref());
         }
-        Ok(())
     }
 
     pub fn execute(&mut self, action: &Action) -> Result<()> {
-        match action {
+        self.pace();
+        self.current = Some(action.clone());
+        let res = match action {
             Action::MoveFile(src, dest_dir) => self.move_file(src, dest_dir),
             Action::MoveDir(src_dir, dest_dir) => self.move_dir(src_dir, dest_dir),
             Action::Delete(path, reason) => self.delete(path, reason),
+        };
+        self.current = None;
+        res
+    }
+
+    /// Logged and counted like a deletion of `path`, but `replace` puts something in its
+    /// place (a link to the kept copy) and must leave `path` untouched when it fails.
+    /// Nothing is freed on failure; the error is returned for the caller to report.
+    pub fn replace_file(&mut self, path: &Path, reason: &str, replace: impl FnOnce() -> io::Result<()>) -> io::Result<()> {
+        self.pace();
+        self.event("DELETE", path, None, Some(reason));
+        let size = path_size(path);
+        if self.apply {
+            replace()?;
         }
+        self.freed_bytes += size;
+        Ok(())
     }
 
-    pub fn prune_empty_dirs(&mut self, root: &Path, skip_roots: &std::collections::HashSet<PathBuf>) -> Result<()> {
+    /// Remove empty directories bottom-up. With a `junk` matcher, directories holding nothing
+    /// but junk files (and directories pruned before them) go too, junk included; with a
+    /// `quarantine` that junk is moved there instead of deleted.
+    pub fn prune_empty_dirs(&mut self, root: &Path, skip_roots: &HashSet<PathBuf>, junk: Option<&GlobSet>, mut quarantine: Option<&mut Quarantine>) -> Result<()> {
+        let mut pruned: HashSet<PathBuf> = HashSet::new();
         // Walk bottom-up to remove empties
         for entry in walkdir::WalkDir::new(root).min_depth(1).max_depth(usize::MAX).contents_first(true) {
             let entry = match entry {
@@ -53,147 +447,679 @@ impl ActionEngine {
             if skip_roots.iter().any(|p| path.starts_with(p)) {
                 continue;
             }
-            if entry.file_type().is_dir() {
+            if !entry.file_type().is_dir() || self.is_protected(root, &path) {
+                continue;
+            }
+            let Some(matcher) = junk else {
                 if is_dir_empty(&path)? {
-                    self.log(format!("PRUNE {}", display(&path)));
+                    self.event("PRUNE", &path, None, None);
                     if self.apply {
                         let _ = fs::remove_dir(&path);
                     }
                 }
+                continue;
+            };
+            let Some(junk_files) = junk_only_contents(&path, matcher, &pruned)? else { continue };
+            for file in junk_files {
+                let action = Action::Delete(file, "junk in otherwise empty dir".to_string());
+                match quarantine.as_deref_mut() {
+                    Some(q) => self.execute(&q.rewrite(action))?,
+                    None => self.execute(&action)?,
+                }
+            }
+            self.event("PRUNE", &path, None, None);
+            if self.apply {
+                let _ = fs::remove_dir(&path);
+            }
+            pruned.insert(path);
+        }
+        Ok(())
+    }
+
+    /// Collapse single-child directory chains below `category_root` by promoting the child
+    /// one level up (bottom-up, so `a/b/file` ends as `file`). Never touches `category_root` itself.
+    pub fn flatten_single_child_dirs(&mut self, category_root: &Path) -> Result<()> {
+        if !category_root.is_dir() { return Ok(()); }
+        let dirs: Vec<PathBuf> = walkdir::WalkDir::new(category_root).min_depth(1).contents_first(true)
+            .into_iter().flatten()
+            .filter(|e| e.file_type().is_dir())
+            .map(|e| e.into_path())
+            .collect();
+        for dir in dirs {
+            let Some(child) = single_child(&dir) else { continue };
+            let parent = dir.parent().unwrap_or(category_root);
+            let name = child.file_name().unwrap_or_default();
+            let mut dest = parent.join(name);
+            self.event("FLATTEN", &child, Some(&dest), None);
+            if !self.apply { continue; }
+            // The child may share its wrapper's name: move the wrapper aside first
+            let mut wrapper = dir.clone();
+            if dest == dir {
+                let mut aside = name.to_os_string();
+                aside.push(".flatten");
+                wrapper = unique_dir_dest(&dir.with_file_name(aside));
+                fs::rename(&dir, &wrapper).context("rename wrapper dir")?;
+            }
+            let child = wrapper.join(name);
+            dest = if child.is_dir() { unique_dir_dest(&dest) } else { unique_dest_path(&dest) };
+            match fs::rename(&child, &dest) {
+                Ok(_) => { let _ = fs::remove_dir(&wrapper); }
+                Err(err) => self.log(format!("ERROR flattening {}: {}", display(&child), err)),
             }
         }
         Ok(())
     }
 
     fn move_file(&mut self, src: &Path, dest_dir: &Path) -> Result<()> {
-        let file_name = src.file_name().unwrap_or_default();
-        let mut dest_path = dest_dir.join(file_name);
+        let mut dest_path = file_dest(src, dest_dir, self.normalize);
+        // Already there: a rename onto itself would only add a suffix
+        if same_location(src, &dest_path) {
+            return Ok(());
+        }
+
+        if let Some(open) = &self.open_files {
+            let abs = fs::canonicalize(src).unwrap_or_else(|_| src.to_path_buf());
+            if open.contains(&abs) {
+                self.log(format!("WARN skipping {} (open by another process)", display(src)));
+                return Ok(());
+            }
+        }
 
-        // Ensure dest dir exists
-        self.log(format!("MOVE {} -> {}", display(src), display(&dest_path)));
+        if self.dry_run_preview() {
+            dest_path = self.claim(src, dest_path, false);
+        } else if self.apply {
+            dest_path = unique_dest_path(&dest_path);
+        }
+        self.event(if self.copy { "COPY" } else { "MOVE" }, src, Some(&dest_path), None);
         if self.apply {
+            // Ensure dest dir exists
             fs::create_dir_all(dest_dir).context("create dest dir")?;
-            dest_path = unique_dest_path(&dest_path);
-            match fs::rename(src, &dest_path) {
-                Ok(_) => {}
+            if self.copy {
+                if let Err(err) = self.copy_keeping_links(src, &dest_path) {
+                    self.log(format!("ERROR copying {}: {}", display(src), err));
+                    return Ok(());
+                }
+                if self.fsync {
+                    sync_to_disk(&dest_path).context("fsync copy")?;
+                }
+                if self.verify_copies && !self.same_contents(src, &dest_path) {
+                    self.log(format!("ERROR copy of {} does not match the source; copy removed", display(src)));
+                    fs::remove_file(&dest_path).ok();
+                } else {
+                    self.place(&dest_path);
+                }
+                return Ok(());
+            }
+            match rename_no_clobber(src, &dest_path) {
+                Ok(used) => {
+                    if used != dest_path {
+                        self.log(format!("WARN {} appeared meanwhile; moved to {} instead", display(&dest_path), display(&used)));
+                    }
+                    self.place(&used);
+                }
                 Err(err) if is_cross_device(&err) && self.allow_cross_device => {
                     // Fallback to copy+remove (can be expensive on nearly full disks)
-                    fs::copy(src, &dest_path).context("copy across device")?;
+                    self.copy_keeping_links(src, &dest_path).context("copy across device")?;
+                    if self.fsync {
+                        sync_to_disk(&dest_path).context("fsync copy")?;
+                    }
+                    if self.verify_copies && !self.same_contents(src, &dest_path) {
+                        self.log(format!("ERROR copy of {} does not match the source; source kept", display(src)));
+                        fs::remove_file(&dest_path).ok();
+                        return Ok(());
+                    }
                     fs::remove_file(src).ok();
+                    self.place(&dest_path);
                 }
+                Err(err) if is_cross_device(&err) => self.skip_cross_device(src),
                 Err(err) => {
                     self.log(format!("ERROR moving {}: {}", display(src), err));
                 }
             }
+        } else if let Some(shadow) = self.shadow_path(&dest_path) {
+            let parent = shadow.parent().unwrap_or_else(|| Path::new("."));
+            fs::create_dir_all(parent).context("create simulate dir")?;
+            let unique = unique_dest_path(&shadow);
+            fs::File::create(&unique).context("create simulate placeholder")?;
+            self.log_simulated(&shadow, &unique);
+        }
+        Ok(())
+    }
+
+    /// Copy `src` to `dest`, unless another link of the same source file was copied before:
+    /// then `dest` becomes a hardlink of that copy, so linked files stay one file on the
+    /// other side too (falling back to a plain copy if the link can't be made).
+    fn copy_keeping_links(&mut self, src: &Path, dest: &Path) -> io::Result<()> {
+        let md = fs::metadata(src)?;
+        let id = file_id(&md);
+        // The first copy may have taken the only other link with it: look up every file
+        let earlier = id.and_then(|id| self.link_copies.lock().unwrap().get(&id).cloned());
+        if let Some(first) = earlier.filter(|f| fs::metadata(f).is_ok_and(|m| m.len() == md.len())) {
+            if fs::hard_link(&first, dest).is_ok() {
+                self.log(format!("LINK {} -> {} (hardlink of {})", display(src), display(dest), display(&first)));
+                return Ok(());
+            }
+        }
+        copy_file(src, dest, self.throttle.as_deref())?;
+        if let Some(id) = id.filter(|_| has_other_links(&md)) {
+            self.link_copies.lock().unwrap().insert(id, dest.to_path_buf());
         }
         Ok(())
     }
 
     fn move_dir(&mut self, src_dir: &Path, dest_dir: &Path) -> Result<()> {
+        if same_location(src_dir, dest_dir) {
+            return Ok(());
+        }
+        if self.dedupe_dirs != DedupeDirMode::Off && dest_dir.is_dir() && self.same_tree(src_dir, dest_dir)
+            && !(self.survivor.is_protected(src_dir) && self.survivor.is_protected(dest_dir)) {
+            return self.drop_duplicate_dir(src_dir, dest_dir);
+        }
         let mut dest = dest_dir.to_path_buf();
-        self.log(format!("MOVE-DIR {} -> {}", display(src_dir), display(&dest)));
+        let taken = dest.exists() || self.claimed.contains(&dest);
+        match self.dir_conflict {
+            DirConflict::Skip if taken => {
+                self.log(format!("SKIP {} ({} exists)", display(src_dir), display(&dest)));
+                return Ok(());
+            }
+            // A file in the way still gets the suffix
+            DirConflict::Merge if taken && (dest.is_dir() || !dest.exists()) => {
+                return self.merge_dir(src_dir, &dest);
+            }
+            _ => {}
+        }
+        if self.dry_run_preview() {
+            dest = self.claim(src_dir, dest, true);
+        }
+        self.event(if self.copy { "COPY-DIR" } else { "MOVE-DIR" }, src_dir, Some(&dest), None);
         if self.apply {
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).context("create dest dir")?;
+            }
             // Append suffix if dest exists
             if dest.exists() {
                 dest = unique_dir_dest(&dest);
             }
+            if self.copy {
+                if let Err(err) = copy_dir_recursive(src_dir, &dest, self.fsync, self.throttle.as_deref()) {
+                    self.log(format!("ERROR copying dir {}: {:#}", display(src_dir), err));
+                } else if let Some(bad) = self.verify_copies.then(|| self.first_mismatch(src_dir, &dest)).flatten() {
+                    self.log(format!("ERROR copy of {} does not match the source", display(&bad)));
+                } else {
+                    self.place(&dest);
+                }
+                return Ok(());
+            }
             // Try rename first
             match fs::rename(src_dir, &dest) {
-                Ok(_) => {}
+                Ok(_) => self.place(&dest),
                 Err(err) if is_cross_device(&err) && self.allow_cross_device => {
                     // Cross device dir move: copy recursively then remove
-                    copy_dir_recursive(src_dir, &dest)?;
+                    copy_dir_recursive(src_dir, &dest, self.fsync, self.throttle.as_deref())?;
+                    if self.verify_copies {
+                        if let Some(bad) = self.first_mismatch(src_dir, &dest) {
+                            self.log(format!("ERROR copy of {} does not match the source; source kept", display(&bad)));
+                            return Ok(());
+                        }
+                    }
                     let _ = fs::remove_dir_all(src_dir);
+                    self.place(&dest);
                 }
+                Err(err) if is_cross_device(&err) => self.skip_cross_device(src_dir),
                 Err(err) => {
                     self.log(format!("ERROR moving dir {}: {}", display(src_dir), err));
                 }
             }
+        } else if let Some(shadow) = self.shadow_path(&dest) {
+            let unique = unique_dir_dest(&shadow);
+            fs::create_dir_all(&unique).context("create simulate dir")?;
+            self.log_simulated(&shadow, &unique);
         }
         Ok(())
     }
 
-    fn delete(&mut self, path: &Path, reason: &str) -> Result<()> {
-        self.log(format!("DELETE {} ({})", display(path), reason));
-        if self.apply {
-            if path.is_dir() {
-                let _ = fs::remove_dir_all(path);
+    /// `DirConflict::Merge`: move the children of `src_dir` into the existing `dest` one by one.
+    /// Files take `move_file`'s collision handling, except that one with the same name and
+    /// contents as the file already there is dropped (or, when copying, not copied again);
+    /// subdirectories merge the same way. The emptied `src_dir` is removed afterwards.
+    fn merge_dir(&mut self, src_dir: &Path, dest: &Path) -> Result<()> {
+        self.event(if self.copy { "COPY-MERGE" } else { "MERGE" }, src_dir, Some(dest), None);
+        let mut children: Vec<PathBuf> = match fs::read_dir(src_dir) {
+            Ok(rd) => rd.flatten().map(|e| e.path()).collect(),
+            Err(err) => {
+                self.log(format!("ERROR reading dir {}: {}", display(src_dir), err));
+                return Ok(());
+            }
+        };
+        children.sort();
+        for child in children {
+            let target = dest.join(child.file_name().unwrap_or_default());
+            let is_dir = fs::symlink_metadata(&child).map(|m| m.is_dir()).unwrap_or(false);
+            if is_dir {
+                self.move_dir(&child, &target)?;
+            } else if target.is_file() && self.same_contents(&child, &target) {
+                if self.copy {
+                    self.log(format!("SKIP {} (already in {})", display(&child), display(dest)));
+                } else {
+                    self.delete(&child, &format!("same as {}", display(&target)))?;
+                }
             } else {
-                let _ = fs::remove_file(path);
+                self.move_file(&child, dest)?;
+            }
+        }
+        if self.apply && !self.copy {
+            // Still holds whatever could not be moved (errors, open files)
+            let _ = fs::remove_dir(src_dir);
+        }
+        Ok(())
+    }
+
+    /// `src_dir` is identical to `dest`: delete the copy that loses under `dedupe_dirs`.
+    /// When the incoming one wins, it takes the other's place. Copies never delete anything.
+    fn drop_duplicate_dir(&mut self, src_dir: &Path, dest: &Path) -> Result<()> {
+        if self.copy {
+            self.log(format!("SKIP {} (same as {})", display(src_dir), display(dest)));
+            return Ok(());
+        }
+        let keep_src = match (self.survivor.is_protected(src_dir), self.survivor.is_protected(dest)) {
+            (true, _) => true,
+            (_, true) => false,
+            _ => self.dedupe_dirs == DedupeDirMode::KeepPreferred && self.survivor.prefers(src_dir, dest),
+        };
+        if !keep_src {
+            return self.delete(src_dir, &format!("duplicate dir, same as {}", display(dest)));
+        }
+        self.delete(dest, &format!("duplicate dir, {} is kept", display(src_dir)))?;
+        if !self.apply {
+            // The real move would find the place free
+            self.event("MOVE-DIR", src_dir, Some(dest), None);
+            self.claimed.insert(dest.to_path_buf());
+            return Ok(());
+        }
+        if dest.exists() {
+            // Deleting failed (logged): leave the survivor where it is
+            return Ok(());
+        }
+        self.move_dir(src_dir, dest)
+    }
+
+    /// Whether `a` and `b` hold the same files, directories and symlinks (pointing at the
+    /// same targets), with the same contents.
+    fn same_tree(&self, a: &Path, b: &Path) -> bool {
+        let entries = |root: &Path| -> Vec<(PathBuf, bool, Option<PathBuf>)> {
+            walkdir::WalkDir::new(root).min_depth(1).sort_by_file_name().into_iter().flatten()
+                .map(|e| {
+                    let link = e.path_is_symlink().then(|| fs::read_link(e.path()).ok()).flatten();
+                    (e.path().strip_prefix(root).unwrap_or(e.path()).to_path_buf(), e.file_type().is_file(), link)
+                })
+                .collect()
+        };
+        let ours = entries(a);
+        ours == entries(b) && ours.iter().filter(|(_, file, _)| *file).all(|(rel, _, _)| self.same_contents(&a.join(rel), &b.join(rel)))
+    }
+
+    fn place(&mut self, path: &Path) {
+        if let Some(placed) = self.placed.as_mut() {
+            placed.push(path.to_path_buf());
+        }
+    }
+
+    /// A plain dry run, where nothing on disk shows earlier planned moves (`--simulate` has placeholders).
+    fn dry_run_preview(&self) -> bool {
+        !self.apply && self.simulate.is_none()
+    }
+
+    /// Dry run: the name `dest` would really get, given what exists and what earlier moves
+    /// took. A rename is flagged, since the plain plan line would hide it.
+    fn claim(&mut self, src: &Path, dest: PathBuf, is_dir: bool) -> PathBuf {
+        let claimed = &self.claimed;
+        let taken = |p: &Path| p.exists() || claimed.contains(p);
+        let final_dest = if is_dir { unique_dir_dest_avoiding(&dest, &taken) } else { unique_dest_path_avoiding(&dest, &taken) };
+        if final_dest != dest {
+            self.log(format!("WARN name collision: {} is taken, {} would become {}", display(&dest), display(src), display(&final_dest)));
+        }
+        self.claimed.insert(final_dest.clone());
+        final_dest
+    }
+
+    /// Whether `path` or one of its ancestors up to `root` holds the protect marker.
+    fn is_protected(&self, root: &Path, path: &Path) -> bool {
+        let Some(marker) = &self.protect_marker else { return false };
+        path.ancestors().take_while(|a| a.starts_with(root)).any(|a| a.join(marker).exists())
+    }
+
+    /// Sleep until the next operation is allowed under `set_max_ops_per_sec`. Dry runs don't wait.
+    fn pace(&mut self) {
+        let Some(interval) = self.op_interval.filter(|_| self.apply) else { return };
+        if let Some(elapsed) = self.last_op.map(|t| t.elapsed()) {
+            if elapsed < interval {
+                std::thread::sleep(interval - elapsed);
             }
         }
+        self.last_op = Some(Instant::now());
+    }
+
+    fn skip_cross_device(&mut self, src: &Path) {
+        self.cross_device_skipped += 1;
+        self.log(format!("WARN skipping {} (cross-device; pass --allow-cross-device to copy)", display(src)));
+    }
+
+    fn delete(&mut self, path: &Path, reason: &str) -> Result<()> {
+        self.event("DELETE", path, None, Some(reason));
+        let size = path_size(path);
+        if !self.apply {
+            self.freed_bytes += size;
+            return Ok(());
+        }
+        let res = if path.is_dir() { fs::remove_dir_all(path) } else { fs::remove_file(path) };
+        match res {
+            // Only what is really gone counts (towards --target-free too)
+            Ok(()) => self.freed_bytes += size,
+            Err(err) => self.log(format!("ERROR deleting {}: {}", display(path), err)),
+        }
         Ok(())
     }
 
-    fn log(&mut self, line: String) {
-        println!("{}", line);
+    fn same_contents(&self, a: &Path, b: &Path) -> bool {
+        match (hash_file(a, self.throttle.as_deref()), hash_file(b, self.throttle.as_deref())) {
+            (Ok(ha), Ok(hb)) => ha == hb,
+            _ => false,
+        }
+    }
+
+    /// First regular file under `src` whose copy under `dst` is missing or differs.
+    fn first_mismatch(&self, src: &Path, dst: &Path) -> Option<PathBuf> {
+        walkdir::WalkDir::new(src).into_iter().flatten()
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .find(|p| {
+                let copy = dst.join(p.strip_prefix(src).unwrap_or(p));
+                !self.same_contents(p, &copy)
+            })
+    }
+
+    fn shadow_path(&self, dest: &Path) -> Option<PathBuf> {
+        let (src_root, shadow_root) = self.simulate.as_ref()?;
+        let rel: PathBuf = match dest.strip_prefix(src_root) {
+            Ok(rel) => rel.to_path_buf(),
+            // Destination outside the scanned root: keep only the normal components
+            Err(_) => dest.components().filter(|c| matches!(c, Component::Normal(_))).collect(),
+        };
+        Some(shadow_root.join(rel))
+    }
+
+    fn log_simulated(&mut self, wanted: &Path, got: &Path) {
+        if wanted == got {
+            self.event("SIMULATE", got, None, None);
+        } else {
+            let reason = format!("collision, renamed from {}", display(wanted));
+            self.event("SIMULATE", got, None, Some(&reason));
+        }
+    }
+
+    /// Log one action: `OP src [-> dest] [(reason)]` on stdout, text or JSONL in the log file.
+    fn event(&mut self, op: &str, src: &Path, dest: Option<&Path>, reason: Option<&str>) {
+        let mut line = format!("{} {}", op, display(src));
+        if let Some(d) = dest {
+            line.push_str(&format!(" -> {}", display(d)));
+        }
+        if let Some(r) = reason {
+            line.push_str(&format!(" ({})", r));
+        }
+        let record = LogRecord { op, src: Some(src), dest, reason, message: None, applied: self.apply, timestamp: now_rfc3339() };
+        self.write_log(&line, &record);
+    }
+
+    /// Log a free-form line (errors, notes). In JSONL the first word becomes `op`.
+    pub fn log(&mut self, line: String) {
+        if let Some(message) = line.strip_prefix("ERROR ") {
+            if let Some(action) = &self.current {
+                self.failures.push((action.clone(), message.to_string()));
+            }
+        }
+        if line.starts_with("ERROR") {
+            self.errors += 1;
+        }
+        let (op, message) = line.split_once(' ').unwrap_or((line.as_str(), ""));
+        let record = LogRecord { op, src: None, dest: None, reason: None, message: Some(message), applied: false, timestamp: now_rfc3339() };
+        self.write_log(&line, &record);
+    }
+
+    fn write_log(&mut self, line: &str, record: &LogRecord) {
+        let json = match self.log_format {
+            LogFormat::Text => None,
+            LogFormat::Jsonl => Some(serde_json::to_string(record).unwrap_or_default()),
+        };
+        if let Some(buffer) = self.buffer.as_mut() {
+            buffer.push(BufferedLine { line: line.to_string(), json });
+            return;
+        }
+        self.emit(line, json.as_deref());
+    }
+
+    fn emit(&mut self, line: &str, json: Option<&str>) {
+        if !self.quiet || line.starts_with("ERROR") {
+            // Color the operation word only; the log file below stays plain
+            let (op, rest) = line.split_at(line.find(' ').unwrap_or(line.len()));
+            let style = op_style(op);
+            anstream::println!("{style}{op}{style:#}{rest}");
+        }
         if let Some(f) = self.log_file.as_mut() {
-            let _ = writeln!(f, "{}", line);
+            let _ = writeln!(f, "{}", json.unwrap_or(line));
         }
     }
 }
 
-/// Helpers
+// Helpers
+
+fn op_style(op: &str) -> anstyle::Style {
+    use anstyle::AnsiColor;
+    match op {
+        "MOVE" | "MOVE-DIR" | "MERGE" | "COPY" | "COPY-DIR" | "COPY-MERGE" | "FLATTEN" => AnsiColor::Green.on_default(),
+        "DELETE" => AnsiColor::Red.on_default(),
+        "PRUNE" => AnsiColor::Yellow.on_default(),
+        "ERROR" => AnsiColor::Red.on_default().bold(),
+        "WARN" | "WARN:" => AnsiColor::Yellow.on_default().bold(),
+        _ => anstyle::Style::new(),
+    }
+}
+
+/// Split actions into groups that can run concurrently: one per destination directory
+/// for moves, one per delete.
+fn independent_groups(actions: &[Action]) -> Vec<Vec<&Action>> {
+    let mut groups: Vec<Vec<&Action>> = Vec::new();
+    let mut by_dest: HashMap<&Path, usize> = HashMap::new();
+    for a in actions {
+        let dest = match a {
+            Action::MoveFile(_, dest_dir) => dest_dir.as_path(),
+            Action::MoveDir(_, dest) => dest.parent().unwrap_or(dest),
+            Action::Delete(..) => {
+                groups.push(vec![a]);
+                continue;
+            }
+        };
+        let i = *by_dest.entry(dest).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[i].push(a);
+    }
+    groups
+}
+
+/// Where `Action::MoveFile(src, dest_dir)` puts the file, before any collision suffix.
+fn file_dest(src: &Path, dest_dir: &Path, normalize: Option<NameNormalization>) -> PathBuf {
+    let file_name = src.file_name().unwrap_or_default();
+    match normalize {
+        Some(opts) => dest_dir.join(normalize_file_name(file_name, opts)),
+        None => dest_dir.join(file_name),
+    }
+}
+
+fn now_rfc3339() -> String {
+    time::OffsetDateTime::now_utc().format(&time::format_description::well_known::Rfc3339).unwrap_or_default()
+}
 
 fn display(p: &Path) -> String {
     p.to_string_lossy().into_owned()
 }
 
 fn is_cross_device(err: &io::Error) -> bool {
-    err.kind() == io::ErrorKind::Other && format!("{}", err).contains("EXDEV")
+    err.kind() == io::ErrorKind::CrossesDevices
 }
 
 fn unique_dest_path(path: &Path) -> PathBuf {
-    if !path.exists() { return path.to_path_buf(); }
+    unique_dest_path_avoiding(path, &|p| p.exists())
+}
+
+/// First of `path`, `stem-1.ext`, `stem-2.ext`, ... that is not `taken`.
+fn unique_dest_path_avoiding(path: &Path, taken: &dyn Fn(&Path) -> bool) -> PathBuf {
+    (0..MAX_SUFFIX).map(|i| numbered_path(path, i)).find(|c| !taken(c)).unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Highest `-N` suffix tried before giving up on a free name.
+const MAX_SUFFIX: usize = 10000;
+
+/// `path` itself for 0, else `stem-i.ext`.
+fn numbered_path(path: &Path, i: usize) -> PathBuf {
+    if i == 0 { return path.to_path_buf(); }
     let parent = path.parent().unwrap_or_else(|| Path::new("."));
-    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("file");
-    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
-    for i in 1..10000 {
-        let candidate = if ext.is_empty() {
-            parent.join(format!("{}-{}", stem, i))
-        } else {
-            parent.join(format!("{}-{}.{}", stem, i, ext))
-        };
-        if !candidate.exists() { return candidate; }
+    let stem = path.file_stem().unwrap_or(OsStr::new("file"));
+    // Built from OsStr pieces so non-UTF-8 names survive the suffix
+    let mut name = stem.to_os_string();
+    name.push(format!("-{i}"));
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
     }
-    path.to_path_buf()
+    parent.join(name)
+}
+
+/// Move `src` to `dest` (or the next free `-N` name) without ever replacing a file. The
+/// new name is claimed by hardlinking, which fails atomically if it exists, and the old
+/// one is then removed; there is no gap between checking a name and taking it. Where
+/// hardlinks aren't supported (FAT, some network shares) it falls back to a plain rename
+/// right after an existence check. Returns the name used.
+fn rename_no_clobber(src: &Path, dest: &Path) -> io::Result<PathBuf> {
+    let mut last_err = None;
+    for i in 0..MAX_SUFFIX {
+        let candidate = numbered_path(dest, i);
+        match fs::hard_link(src, &candidate) {
+            Ok(()) => {
+                if let Err(err) = fs::remove_file(src) {
+                    let _ = fs::remove_file(&candidate);
+                    return Err(err);
+                }
+                return Ok(candidate);
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => last_err = Some(err),
+            Err(err) if is_cross_device(&err) || err.kind() == io::ErrorKind::NotFound => return Err(err),
+            Err(_) if candidate.exists() => {}
+            Err(_) => {
+                fs::rename(src, &candidate)?;
+                return Ok(candidate);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| io::Error::from(io::ErrorKind::AlreadyExists)))
 }
 
 fn unique_dir_dest(path: &Path) -> PathBuf {
-    if !path.exists() { return path.to_path_buf(); }
+    unique_dir_dest_avoiding(path, &|p| p.exists())
+}
+
+fn unique_dir_dest_avoiding(path: &Path, taken: &dyn Fn(&Path) -> bool) -> PathBuf {
+    if !taken(path) { return path.to_path_buf(); }
     let parent = path.parent().unwrap_or_else(|| Path::new("."));
-    let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("dir");
+    let base = path.file_name().unwrap_or(OsStr::new("dir"));
     for i in 1..10000 {
-        let candidate = parent.join(format!("{}-{}", name, i));
-        if !candidate.exists() { return candidate; }
+        let mut name = base.to_os_string();
+        name.push(format!("-{i}"));
+        let candidate = parent.join(name);
+        if !taken(&candidate) { return candidate; }
     }
     path.to_path_buf()
 }
 
-fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<()> {
+/// fsync a file and its parent directory so both the data and the entry survive a crash.
+fn sync_to_disk(path: &Path) -> io::Result<()> {
+    fs::File::open(path)?.sync_all()?;
+    sync_dir(path.parent().unwrap_or_else(|| Path::new(".")))
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+// Directories can't be opened for syncing on other platforms
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn copy_dir_recursive(src: &Path, dst: &Path, fsync: bool, throttle: Option<&Throttle>) -> Result<()> {
     std::fs::create_dir_all(dst)?;
+    let mut dirs = vec![dst.to_path_buf()];
     for entry in walkdir::WalkDir::new(src).min_depth(1) {
         let entry = entry?;
         let rel = entry.path().strip_prefix(src).unwrap();
         let target = dst.join(rel);
         if entry.file_type().is_dir() {
             std::fs::create_dir_all(&target)?;
+            dirs.push(target);
         } else if entry.file_type().is_file() {
             if let Some(p) = target.parent() { std::fs::create_dir_all(p)?; }
-            std::fs::copy(entry.path(), &target)?;
+            copy_file(entry.path(), &target, throttle)?;
+            if fsync {
+                fs::File::open(&target)?.sync_all()?;
+            }
         } else if entry.file_type().is_symlink() {
             // replicate symlink where possible
             if let Ok(target_link) = std::fs::read_link(entry.path()) {
-                #[cfg(unix)]
-                std::os::unix::fs::symlink(&target_link, &target).ok();
+                make_symlink(&target_link, &target).ok();
             }
         }
     }
+    if fsync {
+        // Deepest first, so every new entry is durable before its parent
+        for d in dirs.iter().rev() {
+            sync_dir(d)?;
+        }
+        if let Some(parent) = dst.parent() {
+            sync_dir(parent)?;
+        }
+    }
     Ok(())
 }
 
+fn single_child(dir: &Path) -> Option<PathBuf> {
+    let mut rd = std::fs::read_dir(dir).ok()?;
+    let first = rd.next()?.ok()?;
+    if rd.next().is_some() { return None; }
+    Some(first.path())
+}
+
+/// Files of `dir` when every entry is a junk file or an already pruned directory; `None` otherwise.
+fn junk_only_contents(dir: &Path, matcher: &GlobSet, pruned: &HashSet<PathBuf>) -> Result<Option<Vec<PathBuf>>> {
+    let mut junk = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if pruned.contains(&path) {
+            continue;
+        }
+        let md = fs::symlink_metadata(&path)?;
+        if md.is_file() && is_pattern_match(matcher, &path) {
+            junk.push(path);
+        } else {
+            return Ok(None);
+        }
+    }
+    Ok(Some(junk))
+}
+
 fn is_dir_empty(dir: &Path) -> Result<bool> {
-    for e in std::fs::read_dir(dir)? {
+    if let Some(e) = std::fs::read_dir(dir)?.next() {
         let _ = e?;
         return Ok(false);
     }
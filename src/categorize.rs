@@ -1,9 +1,13 @@
 use crate::config::Settings;
-use anyhow::Result;
-use infer;
+use anyhow::{Context, Result};
+use crate::utils::{compound_extension, file_mtime_date};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::collections::HashMap;
 use std::fs;
-use std::io::Read;
-use std::path::Path;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use time::OffsetDateTime;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Category {
@@ -11,92 +15,252 @@ pub enum Category {
     Music,
     Documents,
     Archives,
+    /// Installers and disk images (`iso`, `dmg`, `exe`, `deb`, ...)
+    Software,
     Projects,
     GitRepos,
     Backups,
+    Applications,
     Others,
 }
 
 impl Category {
+    pub const ALL: [Category; 10] = [
+        Category::Media, Category::Music, Category::Documents, Category::Archives, Category::Software,
+        Category::Projects, Category::GitRepos, Category::Backups, Category::Applications, Category::Others,
+    ];
+
+    /// Categories that receive whole directories (moved intact) rather than single files.
+    /// Applications also takes single AppImages, but its bundles must never be taken apart.
+    pub fn is_whole_dir(&self) -> bool {
+        matches!(self, Category::Projects | Category::GitRepos | Category::Backups | Category::Applications)
+    }
+
     pub fn as_dir(&self) -> &'static str {
         match self {
             Category::Media => "Media",
             Category::Music => "Music",
             Category::Documents => "Documents",
             Category::Archives => "Archives",
+            Category::Software => "Software",
             Category::Projects => "Projects",
             Category::GitRepos => "GitRepos",
             Category::Backups => "Backups",
+            Category::Applications => "Applications",
             Category::Others => "Others",
         }
     }
+
+    pub fn from_name(name: &str) -> Option<Category> {
+        Category::ALL.into_iter().find(|c| c.as_dir().eq_ignore_ascii_case(name))
+    }
 }
 
 pub struct Categorizer<'a> {
     settings: &'a Settings,
     use_file_cmd: bool,
+    /// Content signatures outrank the extension
+    trust_content: bool,
+    /// Extension categories in `category_priority` order
+    priority: Vec<Category>,
+    rules: GlobSet,
+    external: Option<ExternalCmd>,
+    /// Dominant category of each parent directory seen, with `context_aware`
+    context: Option<Mutex<HashMap<PathBuf, Option<Category>>>>,
+}
+
+/// User command asked about files the built-in logic leaves in Others. Answers are cached
+/// per extension; files without one run the command each time.
+struct ExternalCmd {
+    program: String,
+    args: Vec<String>,
+    known: Vec<String>,
+    cache: Mutex<HashMap<String, Option<String>>>,
 }
 
 impl<'a> Categorizer<'a> {
     pub fn new(settings: &'a Settings, use_file_cmd: bool) -> Result<Self> {
-        Ok(Self { settings, use_file_cmd })
+        let mut rules = GlobSetBuilder::new();
+        for (pattern, _) in &settings.category_rules {
+            rules.add(Glob::new(pattern).with_context(|| format!("invalid category rule pattern `{pattern}`"))?);
+        }
+        let priority = settings.category_order().into_iter().filter_map(Category::from_name).collect();
+        Ok(Self { settings, use_file_cmd, trust_content: false, priority, rules: rules.build()?, external: None, context: None })
     }
 
-    pub fn categorize_file(&self, path: &Path) -> Result<Category> {
-        let ext = path.extension()
-            .and_then(|s| s.to_str())
-            .map(|s| s.to_lowercase());
+    /// Ask `cmd` (split on whitespace, the file path appended) for the category of files that
+    /// would otherwise go to Others. It must print a known category or rule folder name.
+    pub fn set_external_cmd(&mut self, cmd: Option<&str>) {
+        self.external = cmd.and_then(|cmd| {
+            let mut words = cmd.split_whitespace().map(str::to_string);
+            Some(ExternalCmd {
+                program: words.next()?,
+                args: words.collect(),
+                known: self.settings.category_names().into_iter()
+                    .chain(Category::ALL.iter().map(|c| c.as_dir().to_string()))
+                    .collect(),
+                cache: Mutex::new(HashMap::new()),
+            })
+        });
+    }
 
-        if let Some(ext) = ext {
-            if self.settings.category_exts.get("Media").map_or(false, |v| v.iter().any(|e| e == &ext)) {
-                return Ok(Category::Media);
-            }
-            if self.settings.category_exts.get("Music").map_or(false, |v| v.iter().any(|e| e == &ext)) {
-                return Ok(Category::Music);
-            }
-            if self.settings.category_exts.get("Documents").map_or(false, |v| v.iter().any(|e| e == &ext)) {
-                return Ok(Category::Documents);
+    /// Settings the categorizer was built from.
+    pub fn settings(&self) -> &'a Settings {
+        self.settings
+    }
+
+    /// Sniff every file's content and let a media, audio or archive signature win over its
+    /// extension. Slower: each file is opened.
+    pub fn set_trust_content(&mut self, on: bool) {
+        self.trust_content = on;
+    }
+
+    /// Send files to the category that dominates their directory (by extension: more than
+    /// half of its files and at least three), so a README stays with the photos around it.
+    pub fn set_context_aware(&mut self, on: bool) {
+        self.context = on.then(|| Mutex::new(HashMap::new()));
+    }
+
+    /// Folder the external command picks for `path`, if one is set and its answer is known.
+    pub fn external_target(&self, path: &Path) -> Option<String> {
+        let ext = self.external.as_ref()?;
+        let key = file_extensions(path).into_iter().next();
+        if let Some(key) = &key {
+            if let Some(cached) = ext.cache.lock().unwrap().get(key) {
+                return cached.clone();
             }
-            if self.settings.category_exts.get("Archives").map_or(false, |v| v.iter().any(|e| e == &ext)) {
-                return Ok(Category::Archives);
+        }
+        let answer = ext.ask(path);
+        if let Some(key) = key {
+            ext.cache.lock().unwrap().insert(key, answer.clone());
+        }
+        answer
+    }
+
+    /// Folder of the first `category_rules` glob matching the file name (or the full path).
+    pub fn rule_target(&self, path: &Path) -> Option<&'a str> {
+        if self.rules.is_empty() { return None; }
+        let by_name = path.file_name().map(|n| self.rules.matches(n)).unwrap_or_default();
+        let first = by_name.into_iter().chain(self.rules.matches(path)).min()?;
+        Some(self.settings.category_rules[first].1.as_str())
+    }
+
+    pub fn categorize_file(&self, path: &Path) -> Result<Category> {
+        // Rules routing to a built-in category win over the extension lists
+        if let Some(cat) = self.rule_target(path).and_then(Category::from_name) {
+            return Ok(cat);
+        }
+
+        // Self-contained Linux applications
+        if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("appimage")) {
+            return Ok(Category::Applications);
+        }
+
+        if let Some(cat) = path.parent().and_then(|dir| self.dominant_category(dir)) {
+            return Ok(cat);
+        }
+
+        let by_extension = self.category_by_extension(path);
+        if self.trust_content {
+            // A media, audio or archive signature beats the extension (a JPEG named `.txt`).
+            // Office documents are zip containers, so an archive signature never overrides them.
+            let content = self.category_by_content(path);
+            match (content, by_extension) {
+                (Some(Category::Archives), Some(Category::Documents)) => {}
+                (Some(cat @ (Category::Media | Category::Music | Category::Archives)), _) => return Ok(cat),
+                _ => {}
             }
-            // Code files fall under Projects ONLY when it's a dir; single code files go to Others unless desired otherwise.
+            return Ok(by_extension.or(content).unwrap_or(Category::Others));
+        }
+        if let Some(cat) = by_extension {
+            return Ok(cat);
         }
+        Ok(self.category_by_content(path).unwrap_or(Category::Others))
+    }
 
-        // Try MIME detection by content for ambiguous files
+    /// Category from the file's content: `file(1)` when enabled, then `infer`.
+    fn category_by_content(&self, path: &Path) -> Option<Category> {
         if self.use_file_cmd {
             if let Some(mime) = mime_via_file_cmd(path) {
-                if mime.starts_with("image/") || mime.starts_with("video/") {
-                    return Ok(Category::Media);
-                } else if mime.starts_with("audio/") {
-                    return Ok(Category::Music);
+                if let Some(cat) = self.category_for_mime(&mime) {
+                    return Some(cat);
                 } else if is_document_mime(&mime) {
-                    return Ok(Category::Documents);
+                    return Some(Category::Documents);
                 } else if is_archive_mime(&mime) {
-                    return Ok(Category::Archives);
+                    return Some(Category::Archives);
                 }
             }
         }
         // fallback to infer
-        if let Ok(mut f) = fs::File::open(path) {
-            let mut buf = [0u8; 8192];
-            let n = f.read(&mut buf).unwrap_or(0);
-            let slice = &buf[..n];
-            if let Some(kind) = infer::get(slice) {
-                let mime = kind.mime_type();
-                if mime.starts_with("image/") || mime.starts_with("video/") {
-                    return Ok(Category::Media);
-                } else if mime.starts_with("audio/") {
-                    return Ok(Category::Music);
+        let mut f = fs::File::open(path).ok()?;
+        let mut buf = [0u8; 8192];
+        let n = f.read(&mut buf).unwrap_or(0);
+        // infer knows container magic (Matroska, MP4 boxes, RIFF, ...) regardless of extension
+        let kind = infer::get(&buf[..n])?;
+        self.category_for_mime(kind.mime_type())
+    }
+
+    /// Category from the extension lists alone.
+    fn category_by_extension(&self, path: &Path) -> Option<Category> {
+        // Longer match first: `tar.gz`/`min.js` before `gz`/`js`
+        for ext in file_extensions(path) {
+            for &cat in &self.priority {
+                if self.settings.category_exts.get(cat.as_dir()).is_some_and(|v| v.iter().any(|e| e == &ext)) {
+                    return Some(cat);
                 }
             }
+            // Code files fall under Projects ONLY when it's a dir; single code files go to Others unless desired otherwise.
         }
+        None
+    }
 
-        Ok(Category::Others)
+    /// With `context_aware`, the category holding most files directly in `dir`, if dominant.
+    fn dominant_category(&self, dir: &Path) -> Option<Category> {
+        let context = self.context.as_ref()?;
+        if let Some(cached) = context.lock().unwrap().get(dir) {
+            return *cached;
+        }
+        let mut counts: HashMap<Category, usize> = HashMap::new();
+        let mut files = 0;
+        for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+            if !entry.file_type().is_ok_and(|t| t.is_file()) { continue; }
+            files += 1;
+            if let Some(cat) = self.category_by_extension(&entry.path()) {
+                *counts.entry(cat).or_default() += 1;
+            }
+        }
+        let dominant = counts.into_iter()
+            .find(|&(_, n)| n >= 3 && n * 2 > files)
+            .map(|(cat, _)| cat);
+        context.lock().unwrap().insert(dir.to_path_buf(), dominant);
+        dominant
     }
 
-    /// Detect special directories to be moved as a whole: Backups (home), Projects (.git), bare Git repos.
+    /// Best known date for a file: EXIF `DateTimeOriginal` (with the `exif` feature),
+    /// then MP4/QuickTime `mvhd` creation time, then the filesystem mtime.
+    pub fn file_date(&self, path: &Path) -> Option<OffsetDateTime> {
+        #[cfg(feature = "exif")]
+        if let Some(d) = exif_date(path) {
+            return Some(d);
+        }
+        if let Some(d) = mp4_creation_date(path) {
+            return Some(d);
+        }
+        file_mtime_date(path)
+    }
+
+    fn category_for_mime(&self, mime: &str) -> Option<Category> {
+        let mime = mime.to_lowercase();
+        let name = self.settings.category_for_mime(&mime)?;
+        Category::from_name(name)
+    }
+
+    /// Detect special directories to be moved as a whole: app bundles, Backups (home), Projects (.git), bare Git repos.
     pub fn detect_special_directory(&self, dir: &Path) -> Option<Category> {
+        // macOS app bundle (Foo.app/Contents/MacOS)
+        if dir.extension().is_some_and(|e| e.eq_ignore_ascii_case("app")) && dir.join("Contents").join("MacOS").is_dir() {
+            return Some(Category::Applications);
+        }
         // Bare git repo?
         if is_bare_git_repo(dir) {
             return Some(Category::GitRepos);
@@ -105,20 +269,15 @@ impl<'a> Categorizer<'a> {
         if dir.join(".git").is_dir() {
             return Some(Category::Projects);
         }
-        // Heuristic: many code files?
+        // Heuristic: many code files? (also below, e.g. under src/)
         let mut code_count = 0usize;
-        if let Ok(rd) = dir.read_dir() {
-            for entry in rd.flatten() {
-                let p = entry.path();
-                if p.is_file() {
-                    if let Some(ext) = p.extension().and_then(|s| s.to_str()).map(|s| s.to_lowercase()) {
-                        if self.settings.code_exts.iter().any(|e| e == &ext) {
-                            code_count += 1;
-                            if code_count >= 5 {
-                                return Some(Category::Projects);
-                            }
-                        }
-                    }
+        let walker = walkdir::WalkDir::new(dir).min_depth(1).max_depth(self.settings.code_scan_depth.max(1));
+        for entry in walker.into_iter().flatten() {
+            if !entry.file_type().is_file() { continue; }
+            if file_extensions(entry.path()).iter().any(|ext| self.settings.code_exts.contains(ext)) {
+                code_count += 1;
+                if code_count >= self.settings.code_file_threshold {
+                    return Some(Category::Projects);
                 }
             }
         }
@@ -133,7 +292,7 @@ impl<'a> Categorizer<'a> {
                 }
             }
         }
-        if markers_found >= 3 {
+        if markers_found >= self.settings.home_marker_threshold {
             return Some(Category::Backups);
         }
 
@@ -141,7 +300,34 @@ impl<'a> Categorizer<'a> {
     }
 }
 
-/// Helpers
+impl ExternalCmd {
+    fn ask(&self, path: &Path) -> Option<String> {
+        let out = match std::process::Command::new(&self.program).args(&self.args).arg(path).output() {
+            Ok(out) => out,
+            Err(err) => {
+                println!("WARN: categorizer-cmd `{}` failed for {}: {err}", self.program, path.display());
+                return None;
+            }
+        };
+        let answer = String::from_utf8_lossy(&out.stdout).trim().to_string();
+        if !out.status.success() || answer.is_empty() {
+            return None;
+        }
+        let known = self.known.iter().find(|k| k.eq_ignore_ascii_case(&answer)).cloned();
+        if known.is_none() {
+            println!("WARN: categorizer-cmd answered unknown category `{answer}` for {}; using Others", path.display());
+        }
+        known
+    }
+}
+
+// Helpers
+
+/// Extensions to look up for `path`, longest first: the compound one, then the plain one.
+fn file_extensions(path: &Path) -> Vec<String> {
+    let ext = path.extension().and_then(|s| s.to_str()).map(|s| s.to_lowercase());
+    compound_extension(path).into_iter().chain(ext).collect()
+}
 
 fn mime_via_file_cmd(path: &Path) -> Option<String> {
     use std::process::Command;
@@ -193,3 +379,70 @@ fn is_bare_git_repo(dir: &Path) -> bool {
     }
     false
 }
+
+#[cfg(feature = "exif")]
+fn exif_date(path: &Path) -> Option<OffsetDateTime> {
+    use exif::{In, Tag, Value};
+    let f = fs::File::open(path).ok()?;
+    let exif = exif::Reader::new().read_from_container(&mut std::io::BufReader::new(f)).ok()?;
+    let field = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)
+        .or_else(|| exif.get_field(Tag::DateTime, In::PRIMARY))?;
+    let Value::Ascii(ref v) = field.value else { return None };
+    let dt = exif::DateTime::from_ascii(v.first()?).ok()?;
+    let date = time::Date::from_calendar_date(dt.year as i32, time::Month::try_from(dt.month).ok()?, dt.day).ok()?;
+    let tod = time::Time::from_hms(dt.hour, dt.minute, dt.second).ok()?;
+    Some(time::PrimitiveDateTime::new(date, tod).assume_utc())
+}
+
+/// Extensions of ISO-BMFF containers whose `mvhd` creation time is read.
+const ISO_BMFF_EXTS: [&str; 7] = ["mp4", "m4v", "m4a", "mov", "qt", "3gp", "3g2"];
+
+/// Read the `moov/mvhd` creation time of an ISO-BMFF (MP4/MOV) file.
+fn mp4_creation_date(path: &Path) -> Option<OffsetDateTime> {
+    const MAC_EPOCH_OFFSET: i64 = 2_082_844_800; // 1904-01-01 -> 1970-01-01
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    if !ISO_BMFF_EXTS.contains(&ext.as_str()) { return None; }
+    let mut f = fs::File::open(path).ok()?;
+    let len = f.metadata().ok()?.len();
+    let moov = find_box(&mut f, 0, len, b"moov")?;
+    let mvhd = find_box(&mut f, moov.0, moov.1, b"mvhd")?;
+    f.seek(SeekFrom::Start(mvhd.0)).ok()?;
+    let mut head = [0u8; 12];
+    f.read_exact(&mut head).ok()?;
+    let secs = if head[0] == 1 {
+        i64::try_from(u64::from_be_bytes(head[4..12].try_into().ok()?)).ok()?
+    } else {
+        u32::from_be_bytes(head[4..8].try_into().ok()?) as i64
+    };
+    if secs == 0 { return None; }
+    OffsetDateTime::from_unix_timestamp(secs - MAC_EPOCH_OFFSET).ok()
+}
+
+/// Find a box of type `kind` between `start` and `end`; returns its payload range.
+/// Box sizes come from the file, so a corrupt one ends the search instead of looping.
+fn find_box(f: &mut fs::File, start: u64, end: u64, kind: &[u8; 4]) -> Option<(u64, u64)> {
+    let mut pos = start;
+    while pos.checked_add(8)? <= end {
+        f.seek(SeekFrom::Start(pos)).ok()?;
+        let mut hdr = [0u8; 8];
+        f.read_exact(&mut hdr).ok()?;
+        let mut size = u32::from_be_bytes(hdr[0..4].try_into().ok()?) as u64;
+        let mut header_len = 8;
+        if size == 1 {
+            let mut large = [0u8; 8];
+            f.read_exact(&mut large).ok()?;
+            size = u64::from_be_bytes(large);
+            header_len = 16;
+        } else if size == 0 {
+            size = end - pos;
+        }
+        if size < header_len { return None; }
+        let next = pos.checked_add(size)?;
+        if &hdr[4..8] == kind {
+            return Some((pos + header_len, next.min(end)));
+        }
+        if next <= pos || next > end { return None; }
+        pos = next;
+    }
+    None
+}
@@ -2,86 +2,402 @@ use anyhow::{Context, Result};
 use dirs::config_dir;
 use globset::{Glob, GlobSet, GlobSetBuilder};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::io::{Write, BufRead, BufReader};
 use std::path::{Path, PathBuf};
 
 pub const APP_DIR: &str = "organizer";
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// Single-file config; keys present here override the individual `*.txt` lists.
+pub const CONFIG_TOML: &str = "organizer.toml";
+
+/// Subdirectory of the config dir holding one directory per `--profile`.
+pub const PROFILES_DIR: &str = "profiles";
+
+pub const DEFAULT_CODE_FILE_THRESHOLD: usize = 5;
+pub const DEFAULT_CODE_SCAN_DEPTH: usize = 2;
+pub const DEFAULT_HOME_MARKER_THRESHOLD: usize = 3;
+pub const DEFAULT_PROTECT_MARKER: &str = ".noorganize";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Settings {
     /// category -> extensions
-    pub category_exts: HashMap<String, Vec<String>>,
+    pub category_exts: BTreeMap<String, Vec<String>>,
     /// delete/ignore glob patterns
     pub delete_patterns: Vec<String>,
+    /// directory names holding regenerable build output (deleted whole with `--clean-build-dirs`)
+    pub junk_dirs: Vec<String>,
     /// names used to detect a "home backup" dir
     pub home_markers: Vec<String>,
     /// code project hints: extensions
     pub code_exts: Vec<String>,
+    /// code files needed (within `code_scan_depth`) to treat a directory as a project
+    pub code_file_threshold: usize,
+    /// how deep to look for code files below a candidate directory (1 = direct children only)
+    pub code_scan_depth: usize,
+    /// home markers needed to treat a directory as a home backup
+    pub home_marker_threshold: usize,
+    /// content MIME type (or `type/` prefix) -> category, used by content detection
+    pub mime_categories: Vec<(String, String)>,
+    /// file names never removed as "empty file" (e.g. `.gitkeep`)
+    pub keep_empty_names: Vec<String>,
+    /// name suffixes of downloads still in progress (`.crdownload`, `.part`), never moved or deleted
+    pub incomplete_suffixes: Vec<String>,
+    /// marker file name that protects its directory from moves, cleanup and dedupe (empty = off)
+    pub protect_marker: String,
+    /// category -> destination template relative to the destination root, e.g. `Media/{year}/{year}-{month}`
+    pub path_templates: BTreeMap<String, String>,
+    /// categories (or rule folders) whose files keep their folders below the root instead of being flattened
+    pub preserve_structure: Vec<String>,
+    /// Ordered glob -> folder overrides checked before the extension lists; first match wins
+    pub category_rules: Vec<(String, String)>,
+    /// order in which categories claim an extension listed under more than one of them
+    pub category_priority: Vec<String>,
+    /// category -> folder name used instead of the built-in one, e.g. `Documents = "Dokumente"`
+    pub category_dirs: BTreeMap<String, String>,
+    /// directory the lists were loaded from (and defaults are written to)
+    #[serde(skip)]
+    pub config_dir: PathBuf,
+    /// `--profile` directory whose lists override those in `config_dir`
+    #[serde(skip)]
+    pub profile_dir: Option<PathBuf>,
+}
+
+/// Config base directory: explicit override, else `$XDG_CONFIG_HOME/organizer`,
+/// else the platform config dir (e.g. `~/.config/organizer`).
+pub fn config_base(override_dir: Option<&Path>) -> PathBuf {
+    if let Some(dir) = override_dir {
+        return dir.to_path_buf();
+    }
+    if let Some(xdg) = std::env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        return PathBuf::from(xdg).join(APP_DIR);
+    }
+    config_dir().unwrap_or_else(|| PathBuf::from(".")).join(APP_DIR)
+}
+
+/// Empty lists, but the built-in thresholds and protect marker: zero thresholds would make
+/// every directory a home backup. `Settings::load_or_default` also fills in the lists.
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            category_exts: BTreeMap::new(),
+            delete_patterns: vec![],
+            junk_dirs: vec![],
+            home_markers: vec![],
+            code_exts: vec![],
+            code_file_threshold: DEFAULT_CODE_FILE_THRESHOLD,
+            code_scan_depth: DEFAULT_CODE_SCAN_DEPTH,
+            home_marker_threshold: DEFAULT_HOME_MARKER_THRESHOLD,
+            mime_categories: vec![],
+            keep_empty_names: vec![],
+            incomplete_suffixes: vec![],
+            protect_marker: DEFAULT_PROTECT_MARKER.to_string(),
+            path_templates: BTreeMap::new(),
+            preserve_structure: vec![],
+            category_rules: vec![],
+            category_priority: vec![],
+            category_dirs: BTreeMap::new(),
+            config_dir: PathBuf::new(),
+            profile_dir: None,
+        }
+    }
 }
 
 impl Settings {
-    pub fn load_or_default() -> Result<Self> {
-        let s = Self::default();
-        s.ensure_default_lists_written()?;
-        // After writing defaults (if needed), load them from files
-        let dir = config_dir().unwrap_or_else(|| PathBuf::from(".")).join(APP_DIR);
-        let media = read_lines_into_vec(dir.join("media_extensions.txt")).unwrap_or_else(|_| default_media_exts());
-        let audio = read_lines_into_vec(dir.join("audio_extensions.txt")).unwrap_or_else(|_| default_audio_exts());
-        let docs  = read_lines_into_vec(dir.join("document_extensions.txt")).unwrap_or_else(|_| default_document_exts());
-        let arch  = read_lines_into_vec(dir.join("archive_extensions.txt")).unwrap_or_else(|_| default_archive_exts());
-        let code  = read_lines_into_vec(dir.join("code_extensions.txt")).unwrap_or_else(|_| default_code_exts());
-
-        let mut category_exts = HashMap::new();
+    /// Load the lists from `dir`; missing files fall back to the built-in defaults.
+    pub fn load_or_default(dir: &Path) -> Result<Self> {
+        Self::load_layered(dir, None)
+    }
+
+    /// Load the named profile from `<base>/profiles/<name>/`. Lists missing there come from
+    /// `base`, then from the built-in defaults; both `organizer.toml` files apply, the profile's last.
+    pub fn load_profile(base: &Path, name: &str) -> Result<Self> {
+        let mut parts = Path::new(name).components();
+        if !matches!((parts.next(), parts.next()), (Some(std::path::Component::Normal(_)), None)) {
+            anyhow::bail!("invalid profile name `{name}`");
+        }
+        Self::load_layered(base, Some(base.join(PROFILES_DIR).join(name)))
+    }
+
+    fn load_layered(dir: &Path, profile: Option<PathBuf>) -> Result<Self> {
+        // The profile's copy of a list wins over the base one
+        let read = |file: &str| {
+            profile.as_ref().map(|p| read_lines_into_vec(p.join(file)))
+                .filter(|r| r.is_ok())
+                .unwrap_or_else(|| read_lines_into_vec(dir.join(file)))
+        };
+        let media = read("media_extensions.txt").unwrap_or_else(|_| default_media_exts());
+        let audio = read("audio_extensions.txt").unwrap_or_else(|_| default_audio_exts());
+        let docs  = read("document_extensions.txt").unwrap_or_else(|_| default_document_exts());
+        let arch  = read("archive_extensions.txt").unwrap_or_else(|_| default_archive_exts());
+        let soft  = read("software_extensions.txt").unwrap_or_else(|_| default_software_exts());
+        let code  = read("code_extensions.txt").unwrap_or_else(|_| default_code_exts());
+
+        let mut category_exts = BTreeMap::new();
         category_exts.insert("Media".to_string(), media);
         category_exts.insert("Music".to_string(), audio);
         category_exts.insert("Documents".to_string(), docs);
         category_exts.insert("Archives".to_string(), arch);
+        category_exts.insert("Software".to_string(), soft);
 
-        let delete_patterns = read_lines_into_vec(dir.join("delete_patterns.txt")).unwrap_or_else(|_| default_delete_patterns());
-        let home_markers = read_lines_into_vec(dir.join("home_markers.txt")).unwrap_or_else(|_| default_home_markers());
+        let delete_patterns = read("delete_patterns.txt").unwrap_or_else(|_| default_delete_patterns());
+        let junk_dirs = read("junk_dirs.txt").unwrap_or_else(|_| default_junk_dirs());
+        let home_markers = read("home_markers.txt").unwrap_or_else(|_| default_home_markers());
+        let mime_categories = read("mime_categories.txt")
+            .map(|v| parse_pairs(&v))
+            .unwrap_or_else(|_| parse_pairs(&default_mime_categories()))
+            .into_iter().map(|(m, c)| (m.to_lowercase(), c)).collect();
+        let detection: HashMap<String, String> = read("detection.txt")
+            .map(|v| parse_pairs(&v).into_iter().collect())
+            .unwrap_or_default();
+        let threshold = |key: &str, default: usize| detection.get(key).and_then(|v| v.parse().ok()).unwrap_or(default);
+        let keep_empty_names = read("keep_empty_names.txt").unwrap_or_else(|_| default_keep_empty_names());
+        let incomplete_suffixes = read("incomplete_suffixes.txt").unwrap_or_else(|_| default_incomplete_suffixes());
+        let path_templates = read("path_templates.txt")
+            .map(|v| parse_pairs(&v).into_iter().collect())
+            .unwrap_or_default();
+        let preserve_structure = read("preserve_structure.txt").unwrap_or_default();
+        let category_rules = read("category_rules.txt")
+            .map(|v| parse_pairs(&v))
+            .unwrap_or_default();
+        let category_priority = read("category_priority.txt").unwrap_or_else(|_| default_category_priority());
+        let category_dirs = read("category_dirs.txt")
+            .map(|v| parse_pairs(&v).into_iter().collect())
+            .unwrap_or_default();
 
-        Ok(Self {
+        let mut settings = Self {
             category_exts,
             delete_patterns,
+            junk_dirs,
             home_markers,
             code_exts: code,
-        })
+            code_file_threshold: threshold("code_file_threshold", DEFAULT_CODE_FILE_THRESHOLD),
+            code_scan_depth: threshold("code_scan_depth", DEFAULT_CODE_SCAN_DEPTH),
+            home_marker_threshold: threshold("home_marker_threshold", DEFAULT_HOME_MARKER_THRESHOLD),
+            mime_categories,
+            keep_empty_names,
+            incomplete_suffixes,
+            protect_marker: detection.get("protect_marker").cloned().unwrap_or_else(|| DEFAULT_PROTECT_MARKER.to_string()),
+            path_templates,
+            preserve_structure,
+            category_rules,
+            category_priority,
+            category_dirs,
+            config_dir: dir.to_path_buf(),
+            profile_dir: None,
+        };
+
+        for layer in std::iter::once(dir).chain(profile.as_deref()) {
+            let toml_path = layer.join(CONFIG_TOML);
+            if toml_path.is_file() {
+                settings = settings.overlay_toml(&toml_path)?;
+            }
+        }
+        settings.profile_dir = profile;
+        Ok(settings)
+    }
+
+    /// Merge the keys of a TOML file over these settings (tables one level deep, so
+    /// `[category_exts] Media = [...]` keeps the other categories).
+    fn overlay_toml(self, path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+        let user: toml::Table = text.parse().with_context(|| format!("parse {}", path.display()))?;
+        let toml::Value::Table(mut merged) = toml::Value::try_from(&self)? else {
+            unreachable!("Settings serializes to a table")
+        };
+        for (key, value) in user {
+            match (merged.get_mut(&key), value) {
+                (Some(toml::Value::Table(base)), toml::Value::Table(over)) => base.extend(over),
+                (_, value) => { merged.insert(key, value); }
+            }
+        }
+        let mut settings: Settings = toml::Value::Table(merged).try_into()
+            .with_context(|| format!("invalid settings in {}", path.display()))?;
+        settings.config_dir = self.config_dir;
+        settings.profile_dir = self.profile_dir;
+        Ok(settings)
+    }
+
+    /// Effective settings as TOML, suitable for saving as `organizer.toml`.
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
     }
 
     pub fn ensure_default_lists_written(&self) -> Result<()> {
-        let base = config_dir().unwrap_or_else(|| PathBuf::from(".")).join(APP_DIR);
-        fs::create_dir_all(&base).context("create config dir")?;
+        let base = &self.config_dir;
+        fs::create_dir_all(base).context("create config dir")?;
 
         write_default_if_missing(base.join("media_extensions.txt"), &default_media_exts())?;
         write_default_if_missing(base.join("audio_extensions.txt"), &default_audio_exts())?;
         write_default_if_missing(base.join("document_extensions.txt"), &default_document_exts())?;
         write_default_if_missing(base.join("archive_extensions.txt"), &default_archive_exts())?;
+        write_default_if_missing(base.join("software_extensions.txt"), &default_software_exts())?;
         write_default_if_missing(base.join("code_extensions.txt"), &default_code_exts())?;
         write_default_if_missing(base.join("home_markers.txt"), &default_home_markers())?;
         write_default_if_missing(base.join("delete_patterns.txt"), &default_delete_patterns())?;
+        write_default_if_missing(base.join("junk_dirs.txt"), &default_junk_dirs())?;
+        write_default_if_missing(base.join("mime_categories.txt"), &default_mime_categories())?;
+        write_default_if_missing(base.join("detection.txt"), &default_detection())?;
+        write_default_if_missing(base.join("keep_empty_names.txt"), &default_keep_empty_names())?;
+        write_default_if_missing(base.join("incomplete_suffixes.txt"), &default_incomplete_suffixes())?;
+        write_default_if_missing(base.join("path_templates.txt"), &default_path_templates())?;
+        write_default_if_missing(base.join("category_rules.txt"), &default_category_rules())?;
+        write_default_if_missing(base.join("preserve_structure.txt"), &default_preserve_structure())?;
+        write_default_if_missing(base.join("category_priority.txt"), &default_category_priority())?;
+        write_default_if_missing(base.join("category_dirs.txt"), &default_category_dirs())?;
+        // Profiles start empty: every list comes from the base until copied in and edited
+        if let Some(profile) = &self.profile_dir {
+            fs::create_dir_all(profile).with_context(|| format!("create profile {}", profile.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Directory `file` is read from: the profile's when it has its own copy.
+    fn list_dir(&self, file: &str) -> &Path {
+        match &self.profile_dir {
+            Some(profile) if profile.join(file).is_file() => profile,
+            _ => &self.config_dir,
+        }
+    }
+
+    /// Add `ext` to the extension list of `category` (`Media`, `Music`, `Documents`, `Archives`,
+    /// `Software` or `Code`), both in memory and in its `*_extensions.txt`, so later runs know it too.
+    pub fn learn_extension(&mut self, category: &str, ext: &str) -> Result<()> {
+        let (file, defaults, list) = match category {
+            "Media" => ("media_extensions.txt", default_media_exts(), self.category_exts.entry("Media".into()).or_default()),
+            "Music" => ("audio_extensions.txt", default_audio_exts(), self.category_exts.entry("Music".into()).or_default()),
+            "Documents" => ("document_extensions.txt", default_document_exts(), self.category_exts.entry("Documents".into()).or_default()),
+            "Archives" => ("archive_extensions.txt", default_archive_exts(), self.category_exts.entry("Archives".into()).or_default()),
+            "Software" => ("software_extensions.txt", default_software_exts(), self.category_exts.entry("Software".into()).or_default()),
+            "Code" => ("code_extensions.txt", default_code_exts(), &mut self.code_exts),
+            _ => anyhow::bail!("no extension list for category `{category}`"),
+        };
+        if !list.iter().any(|e| e == ext) {
+            list.push(ext.to_string());
+        }
+        let dir = self.list_dir(file).to_path_buf();
+        let path = dir.join(file);
+        fs::create_dir_all(&dir).context("create config dir")?;
+        // A fresh list would replace the built-in defaults: write those first
+        write_default_if_missing(path.clone(), &defaults)?;
+        let needs_newline = fs::read(&path).is_ok_and(|b| b.last().is_some_and(|&c| c != b'\n'));
+        let mut f = fs::OpenOptions::new().append(true).open(&path).with_context(|| format!("open {}", path.display()))?;
+        if needs_newline {
+            writeln!(f)?;
+        }
+        writeln!(f, "{ext}")?;
         Ok(())
     }
 
-    pub fn delete_matcher(&self) -> Result<GlobSet> {
+    /// Extension categories in the order they claim an extension: `category_priority` first,
+    /// then any category it leaves out.
+    pub fn category_order(&self) -> Vec<&str> {
+        let mut order: Vec<&str> = self.category_priority.iter()
+            .filter_map(|c| self.category_exts.keys().find(|k| k.eq_ignore_ascii_case(c)))
+            .map(String::as_str)
+            .collect();
+        for cat in self.category_exts.keys() {
+            if !order.contains(&cat.as_str()) {
+                order.push(cat);
+            }
+        }
+        order.dedup();
+        order
+    }
+
+    /// Extensions listed under more than one category, as human-readable warnings naming the winner.
+    pub fn extension_conflicts(&self) -> Vec<String> {
+        let mut owners: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for cat in self.category_order() {
+            for ext in &self.category_exts[cat] {
+                let cats = owners.entry(ext).or_default();
+                if !cats.contains(&cat) {
+                    cats.push(cat);
+                }
+            }
+        }
+        owners.into_iter()
+            .filter(|(_, cats)| cats.len() > 1)
+            .map(|(ext, cats)| format!("extension `{ext}` is listed under {}; {} wins (see category_priority.txt)", cats.join(", "), cats[0]))
+            .collect()
+    }
+
+    /// Folder name for the built-in category `category`: its `category_dirs.txt` override,
+    /// else the category name itself.
+    pub fn dir_name<'a>(&'a self, category: &'a str) -> &'a str {
+        self.category_dirs.iter()
+            .find(|(c, _)| c.eq_ignore_ascii_case(category))
+            .map_or(category, |(_, dir)| dir.as_str())
+    }
+
+    /// Whether files routed to `folder` keep their path below the root.
+    pub fn preserves_structure(&self, folder: &str) -> bool {
+        self.preserve_structure.iter().any(|c| c.eq_ignore_ascii_case(folder))
+    }
+
+    /// Whether `name` marks a download still in progress (case-insensitive suffix match).
+    pub fn is_incomplete(&self, name: &std::ffi::OsStr) -> bool {
+        let name = name.to_string_lossy().to_lowercase();
+        self.incomplete_suffixes.iter().any(|s| name.ends_with(&s.to_lowercase()))
+    }
+
+    /// Whether `dir` holds the protect marker, i.e. must be left exactly as it is.
+    pub fn is_protected_dir(&self, dir: &Path) -> bool {
+        !self.protect_marker.is_empty() && dir.join(&self.protect_marker).exists()
+    }
+
+    /// Compile the delete patterns. Malformed patterns are skipped and returned as
+    /// human-readable problems (`file:line: ...`) so the caller can warn or abort.
+    pub fn delete_matcher(&self) -> Result<(GlobSet, Vec<String>)> {
         let mut builder = GlobSetBuilder::new();
+        let mut problems = vec![];
+        let file = self.list_dir("delete_patterns.txt").join("delete_patterns.txt");
         for pat in &self.delete_patterns {
-            // tolerant: skip malformed
-            if let Ok(gl) = Glob::new(pat) {
-                builder.add(gl);
+            match Glob::new(pat) {
+                Ok(gl) => { builder.add(gl); }
+                Err(err) => {
+                    let line = line_of(&file, pat).map(|n| format!(":{n}")).unwrap_or_default();
+                    problems.push(format!("delete_patterns.txt{line}: invalid pattern `{pat}`: {}", err.kind()));
+                }
             }
         }
-        Ok(builder.build()?)
+        Ok((builder.build()?, problems))
+    }
+
+    /// Look up the category for a content MIME type. Exact entries win over `type/` prefixes.
+    pub fn category_for_mime(&self, mime: &str) -> Option<&str> {
+        if let Some((_, cat)) = self.mime_categories.iter().find(|(m, _)| m == mime) {
+            return Some(cat);
+        }
+        self.mime_categories.iter()
+            .filter(|(m, _)| m.ends_with('/') && mime.starts_with(m.as_str()))
+            .max_by_key(|(m, _)| m.len())
+            .map(|(_, cat)| cat.as_str())
     }
 
+    /// Top-level destination folder names, including folders that path templates route to,
+    /// so re-runs never rescan (and re-nest) organized output.
     pub fn category_names(&self) -> Vec<String> {
-        vec!["Media","Music","Documents","Archives","Projects","GitRepos","Backups","Others"]
-            .into_iter().map(|s| s.to_string()).collect()
+        let mut names: Vec<String> = ["Media","Music","Documents","Archives","Software","Projects","GitRepos","Backups","Applications","Others"]
+            .into_iter().map(|c| self.dir_name(c).to_string()).collect();
+        let rule_targets = self.category_rules.iter().map(|(_, folder)| folder);
+        for template in self.path_templates.values().chain(rule_targets) {
+            if let Some(std::path::Component::Normal(first)) = Path::new(template).components().next() {
+                let first = first.to_string_lossy();
+                // A placeholder in the first component can't be known up front
+                if !first.contains('{') {
+                    names.push(first.into_owned());
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
     }
 }
 
-/// Helpers
+// Helpers
 
 fn write_default_if_missing(path: PathBuf, lines: &Vec<String>) -> Result<()> {
     if !path.exists() {
@@ -95,6 +411,12 @@ fn write_default_if_missing(path: PathBuf, lines: &Vec<String>) -> Result<()> {
     Ok(())
 }
 
+/// 1-based line number of the first line in `p` equal to `needle` (after trimming).
+fn line_of(p: &Path, needle: &str) -> Option<usize> {
+    let text = fs::read_to_string(p).ok()?;
+    text.lines().position(|l| l.trim() == needle).map(|i| i + 1)
+}
+
 fn read_lines_into_vec<P: AsRef<Path>>(p: P) -> Result<Vec<String>> {
     let f = fs::File::open(&p)?;
     let br = BufReader::new(f);
@@ -107,6 +429,14 @@ fn read_lines_into_vec<P: AsRef<Path>>(p: P) -> Result<Vec<String>> {
     Ok(v)
 }
 
+/// Parse "key value" lines (whitespace separated); malformed lines are skipped.
+fn parse_pairs(lines: &[String]) -> Vec<(String, String)> {
+    lines.iter().filter_map(|l| {
+        let mut it = l.split_whitespace();
+        Some((it.next()?.to_string(), it.next()?.to_string()))
+    }).collect()
+}
+
 fn default_media_exts() -> Vec<String> {
     vec!["jpg","jpeg","png","gif","bmp","tiff","tif","webp","heic","heif","raw","cr2","nef","arw","raf","dng",
          "mp4","mkv","avi","mov","flv","webm","mpeg","mpg","m4v","3gp","3g2"]
@@ -121,7 +451,11 @@ fn default_document_exts() -> Vec<String> {
         .into_iter().map(|s| s.to_string()).collect()
 }
 fn default_archive_exts() -> Vec<String> {
-    vec!["zip","tar","gz","tgz","bz2","tbz","xz","7z","rar","iso","img"]
+    vec!["zip","tar","gz","tgz","bz2","tbz","xz","7z","rar"]
+        .into_iter().map(|s| s.to_string()).collect()
+}
+fn default_software_exts() -> Vec<String> {
+    vec!["iso","img","dmg","exe","msi","deb","rpm","apk","pkg"]
         .into_iter().map(|s| s.to_string()).collect()
 }
 fn default_code_exts() -> Vec<String> {
@@ -148,8 +482,103 @@ fn default_delete_patterns() -> Vec<String> {
         "**/*.swo",
         "**/*~",
         "**/.Trash/**",
+        "**/$RECYCLE.BIN/**",
         "**/*.part",
         "**/~$*",
         "**/desktop.ini",
     ].into_iter().map(|s| s.to_string()).collect()
 }
+fn default_junk_dirs() -> Vec<String> {
+    vec!["node_modules","target","build",".venv","venv","__pycache__",".pytest_cache",".gradle",".tox"]
+        .into_iter().map(|s| s.to_string()).collect()
+}
+
+fn default_mime_categories() -> Vec<String> {
+    vec![
+        "image/ Media",
+        "video/ Media",
+        "audio/ Music",
+        // containers often found without (or with a wrong) extension
+        "video/x-matroska Media",
+        "video/webm Media",
+        "video/mp4 Media",
+        "video/quicktime Media",
+        "video/x-msvideo Media",
+        "video/mpeg Media",
+        "video/x-flv Media",
+        "audio/x-wav Music",
+        "audio/m4a Music",
+        "application/pdf Documents",
+        "application/zip Archives",
+        "application/x-tar Archives",
+        "application/gzip Archives",
+        "application/x-7z-compressed Archives",
+        "application/x-rar-compressed Archives",
+        "application/vnd.rar Archives",
+        "application/x-xz Archives",
+        "application/x-iso9660-image Software",
+        "application/x-apple-diskimage Software",
+        "application/vnd.microsoft.portable-executable Software",
+        "application/x-dosexec Software",
+        "application/x-msi Software",
+        "application/vnd.debian.binary-package Software",
+        "application/x-rpm Software",
+    ].into_iter().map(|s| s.to_string()).collect()
+}
+fn default_detection() -> Vec<String> {
+    vec![
+        format!("code_file_threshold {DEFAULT_CODE_FILE_THRESHOLD}"),
+        format!("code_scan_depth {DEFAULT_CODE_SCAN_DEPTH}"),
+        format!("home_marker_threshold {DEFAULT_HOME_MARKER_THRESHOLD}"),
+        format!("protect_marker {DEFAULT_PROTECT_MARKER}"),
+    ]
+}
+fn default_keep_empty_names() -> Vec<String> {
+    vec![".gitkeep",".keep",".gitignore","__init__.py","py.typed",".nomedia",".lock","LOCK"]
+        .into_iter().map(|s| s.to_string()).collect()
+}
+fn default_incomplete_suffixes() -> Vec<String> {
+    vec![".part",".crdownload",".download",".partial",".opdownload"]
+        .into_iter().map(|s| s.to_string()).collect()
+}
+fn default_path_templates() -> Vec<String> {
+    // Commented out by default: categories stay flat unless a template is enabled
+    vec![
+        "# Category Template  (placeholders: {year} {month} {day}, from the file date)",
+        "# Media Media/{year}/{year}-{month}",
+    ].into_iter().map(|s| s.to_string()).collect()
+}
+fn default_preserve_structure() -> Vec<String> {
+    vec![
+        "# Categories whose files keep their folders below the root (Trip/2020/a.jpg ->",
+        "# Media/Trip/2020/a.jpg) instead of landing directly in the category folder, e.g.",
+        "# Media",
+    ].into_iter().map(|s| s.to_string()).collect()
+}
+
+fn default_category_priority() -> Vec<String> {
+    vec![
+        "# Categories in the order they claim an extension listed under several of them",
+        "Media",
+        "Music",
+        "Documents",
+        "Software",
+        "Archives",
+    ].into_iter().map(|s| s.to_string()).collect()
+}
+
+fn default_category_dirs() -> Vec<String> {
+    vec![
+        "# Category Folder  (renames a category's folder; re-runs skip the new name)",
+        "# Documents Dokumente",
+    ].into_iter().map(|s| s.to_string()).collect()
+}
+
+fn default_category_rules() -> Vec<String> {
+    vec![
+        "# Glob Folder  (checked in order before the extension lists; first match wins)",
+        "# Patterns without a `/` match the file name, others the full path.",
+        "# The folder may be a category (Documents, Media, ...) or any other name.",
+        "# invoice_*.pdf Finance",
+    ].into_iter().map(|s| s.to_string()).collect()
+}
@@ -1,40 +1,166 @@
-use crate::actions::{Action, ActionEngine};
-use anyhow::Result;
+use crate::actions::{stop_requested, Action, ActionEngine};
+use crate::hash_cache::HashCache;
+use crate::utils::{device_id, extension_allowed, file_id, hash_file, hash_file_mmap, human_size, is_hidden_name, make_symlink, mtime_ns, SizeMetric, Throttle, ThrottledRead};
+use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use rayon::prelude::*;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::hash::Hash;
+use std::io::{IsTerminal, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use xxhash_rust::xxh3::Xxh3;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum DedupeMethod {
     Name,
     Size,
     Hash,
+    /// Near-duplicate images within a Hamming distance (needs the `perceptual` feature)
+    Perceptual,
 }
 
+/// Whether duplicates may be collapsed across top-level category folders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DedupeScope {
+    Global,
+    WithinCategory,
+    /// Only files side by side in one directory (`a.jpg` and `a (copy).jpg`)
+    SameDirectory,
+    /// Only files below the same first N directories under the root: with 2,
+    /// `Music/Album A/…` and `Music/Album B/…` are deduped apart
+    WithinDepth(usize),
+}
+
+/// How `DedupeMethod::Name` compares file names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DedupeNameMode {
+    Exact,
+    /// `photo.JPG` matches `photo.jpg`
+    CaseInsensitive,
+    /// Name without its last extension: `photo.jpg` matches `photo.jpeg`
+    Stem,
+}
+
+/// Default maximum Hamming distance between perceptual hashes of "the same" image.
+pub const DEFAULT_PERCEPTUAL_DISTANCE: u32 = 8;
+
+/// Files at least this large are hashed through a memory map, in parallel.
+pub const DEFAULT_MMAP_THRESHOLD: u64 = 16 << 20;
+
+/// Extensions considered for perceptual hashing.
+const IMAGE_EXTS: [&str; 8] = ["jpg", "jpeg", "png", "gif", "bmp", "tif", "tiff", "webp"];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum DedupeMode {
     Delete,
     Hardlink,
     Symlink,
+    /// Copy-on-write clone of the kept file (Btrfs, XFS, APFS); falls back to a hardlink
+    Reflink,
+}
+
+/// One duplicate group as reported by `--dedup-report`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DupGroup {
+    pub keep: PathBuf,
+    pub size: u64,
+    pub duplicates: Vec<DupEntry>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DupEntry {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// What the hashing stages of a scan did, to see where the time goes.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct DedupeStats {
+    /// Files up for hashing
+    pub files: usize,
+    /// Distinct sizes among them
+    pub size_buckets: usize,
+    /// Sizes shared by two or more files; only the files in these were read
+    pub shared_size_buckets: usize,
+    /// Files whose hashes came from the hash cache instead of being read
+    pub cached_hashes: usize,
+    pub quick_hashes: usize,
+    pub full_hashes: usize,
+    pub bytes_read: u64,
+}
+
+/// A file and the other paths hardlinked to it.
+type Links<'a> = (&'a FileInfo, Vec<&'a FileInfo>);
+
 #[derive(Debug, Clone)]
 struct FileInfo {
     path: PathBuf,
-    name: String,
+    name: OsString,
+    /// path below the scanned root
+    rel: PathBuf,
     size: u64,
+    mtime_ns: u64,
+    quick: Option<u64>,
     hash: Option<[u8; 32]>,
+    phash: Option<Vec<u8>>,
+    /// (device, inode) where the platform has them; equal ids are hardlinks of one file
+    file_id: Option<(u64, u64)>,
 }
 
+/// Bytes read from each end of a file for the quick pre-pass hash.
+const QUICK_CHUNK: u64 = 64 * 1024;
+
 impl FileInfo {
-    fn compute_hash(&mut self) -> Result<()> {
+    /// Top-level folder below the scanned root (the category), if the file is inside one.
+    fn category(&self) -> Option<&OsStr> {
+        let mut comps = self.rel.components();
+        let first = comps.next()?;
+        comps.next().map(|_| first.as_os_str())
+    }
+
+    /// The first `depth` directories of its path below the scanned root (fewer for a file
+    /// higher up).
+    fn subtree(&self, depth: usize) -> Vec<&OsStr> {
+        let dir = self.rel.parent().unwrap_or(Path::new(""));
+        dir.components().take(depth).map(|c| c.as_os_str()).collect()
+    }
+
+    /// Under `prefix`: absolute prefixes match the full path, relative ones the path below the root.
+    fn is_under(&self, prefix: &Path) -> bool {
+        if prefix.is_absolute() { self.path.starts_with(prefix) } else { self.rel.starts_with(prefix) }
+    }
+
+    /// xxh3 over the size plus the first and last 64 KiB: cheap, only used to find candidates.
+    fn compute_quick_hash(&mut self, throttle: Option<&Throttle>) -> Result<()> {
+        let mut hasher = Xxh3::new();
+        hasher.update(&self.size.to_le_bytes());
+        let mut f = ThrottledRead::new(fs::File::open(&self.path)?, throttle);
+        let mut buf = vec![0u8; QUICK_CHUNK as usize];
+        let n = read_full(&mut f, &mut buf)?;
+        hasher.update(&buf[..n]);
+        if self.size > 2 * QUICK_CHUNK {
+            f.get_mut().seek(SeekFrom::Start(self.size - QUICK_CHUNK))?;
+            let n = read_full(&mut f, &mut buf)?;
+            hasher.update(&buf[..n]);
+        } else if self.size > QUICK_CHUNK {
+            let n = read_full(&mut f, &mut buf)?;
+            hasher.update(&buf[..n]);
+        }
+        self.quick = Some(hasher.digest());
+        Ok(())
+    }
+
+    /// Full blake3; files of `mmap_threshold` bytes or more (0 = never) are memory-mapped
+    /// unless throttled, since the throttle has to see every read.
+    fn compute_hash(&mut self, throttle: Option<&Throttle>, mmap_threshold: u64) -> Result<()> {
         if self.hash.is_none() {
-            let mut hasher = blake3::Hasher::new();
-            let mut f = fs::File::open(&self.path)?;
-            std::io::copy(&mut f, &mut hasher)?;
-            self.hash = Some(*hasher.finalize().as_bytes());
+            let mmap = throttle.is_none() && mmap_threshold > 0 && self.size >= mmap_threshold;
+            self.hash = Some(if mmap { hash_file_mmap(&self.path)? } else { hash_file(&self.path, throttle)? });
         }
         Ok(())
     }
@@ -44,102 +170,792 @@ impl FileInfo {
 pub struct DedupePlan {
     methods: Vec<DedupeMethod>,
     files: Vec<FileInfo>,
+    interactive: bool,
+    full_hash: bool,
+    confirm_with_hash: bool,
+    perceptual_distance: u32,
+    scope: DedupeScope,
+    max_size: Option<u64>,
+    size_metric: SizeMetric,
+    throttle: Option<Throttle>,
+    mmap_threshold: u64,
+    keep_prefix: Option<PathBuf>,
+    protect: Option<GlobSet>,
+    inherit_mtime: bool,
+    cross_device_symlink: bool,
+    preview_limit: Option<usize>,
+    name_mode: DedupeNameMode,
+    protect_marker: Option<String>,
+    /// Lowercase name suffixes left out of the scan (downloads in progress)
+    skip_suffixes: Vec<String>,
+    /// `--only-ext`/`--ignore-ext`
+    only_extensions: Vec<String>,
+    ignore_extensions: Vec<String>,
+    one_filesystem: bool,
+    include_hidden: bool,
+    /// Files, or directories with everything below them, left out of the scan
+    skip_paths: HashSet<PathBuf>,
+    hash_cache: Option<HashCache>,
+    stats: DedupeStats,
 }
 
 impl DedupePlan {
     pub fn new(methods: Vec<DedupeMethod>) -> Self {
-        Self { methods, files: vec![] }
+        Self { methods, files: vec![], interactive: false, full_hash: false, confirm_with_hash: false, perceptual_distance: DEFAULT_PERCEPTUAL_DISTANCE, scope: DedupeScope::Global, max_size: None, size_metric: SizeMetric::Logical, throttle: None, mmap_threshold: DEFAULT_MMAP_THRESHOLD, keep_prefix: None, protect: None, inherit_mtime: false, cross_device_symlink: false, preview_limit: None, name_mode: DedupeNameMode::Exact, protect_marker: None, skip_suffixes: vec![], only_extensions: vec![], ignore_extensions: vec![], one_filesystem: false, include_hidden: false, skip_paths: HashSet::new(), hash_cache: None, stats: DedupeStats::default() }
+    }
+
+    /// Restrict groups to a single category folder, or collapse across all of them.
+    pub fn set_scope(&mut self, scope: DedupeScope) {
+        self.scope = scope;
+    }
+
+    /// Leave files larger than `max` out of the scan entirely (never hashed, never grouped).
+    pub fn set_max_size(&mut self, max: Option<u64>) {
+        self.max_size = max;
+    }
+
+    /// Size used by `set_max_size`: logical length or allocated blocks. Files are always
+    /// grouped by their logical length.
+    pub fn set_size_metric(&mut self, metric: SizeMetric) {
+        self.size_metric = metric;
+    }
+
+    /// Prefer a group member under `prefix` as the survivor (absolute, or relative to the scanned root).
+    pub fn set_keep_prefix(&mut self, prefix: Option<PathBuf>) {
+        self.keep_prefix = prefix;
+    }
+
+    /// Never act on files matching any of `patterns` (against the full path or the path
+    /// below the root). A protected member is always the survivor; further protected
+    /// members of the same group are left out of it.
+    pub fn set_protect(&mut self, patterns: &[String]) -> Result<()> {
+        self.protect = protect_set(patterns)?;
+        Ok(())
+    }
+
+    /// Give each survivor the oldest mtime of its group, so date-based sorting still sees
+    /// when the content first appeared.
+    pub fn set_inherit_mtime(&mut self, on: bool) {
+        self.inherit_mtime = on;
+    }
+
+    /// Hardlink mode: symlink a duplicate on another filesystem than its survivor (and the
+    /// group's other copies there) instead of keeping it.
+    pub fn set_cross_device_symlink(&mut self, on: bool) {
+        self.cross_device_symlink = on;
+    }
+
+    /// How names are compared when grouping by `DedupeMethod::Name`.
+    pub fn set_name_mode(&mut self, mode: DedupeNameMode) {
+        self.name_mode = mode;
+    }
+
+    /// Print the actions of the first `limit` groups only; the rest still run, logged to the
+    /// log file alone, and are summed up in one line.
+    pub fn set_preview_limit(&mut self, limit: Option<usize>) {
+        self.preview_limit = limit;
+    }
+
+    /// Never scan directories holding a file named `marker` (empty = no marker).
+    pub fn set_protect_marker(&mut self, marker: &str) {
+        self.protect_marker = (!marker.is_empty()).then(|| marker.to_string());
+    }
+
+    /// Leave files (and directories) whose name ends in one of `suffixes` out of the scan.
+    pub fn set_skip_suffixes(&mut self, suffixes: &[String]) {
+        self.skip_suffixes = suffixes.iter().map(|s| s.to_lowercase()).collect();
+    }
+
+    /// Only scan files whose extension is in `only` (when not empty) and not in `ignore`.
+    pub fn set_extensions(&mut self, only: &[String], ignore: &[String]) {
+        self.only_extensions = only.to_vec();
+        self.ignore_extensions = ignore.to_vec();
+    }
+
+    /// Also scan dotfiles and dot-directories (`.ssh`), which are left out by default.
+    pub fn set_include_hidden(&mut self, on: bool) {
+        self.include_hidden = on;
+    }
+
+    /// Leave `paths` out of the scan: files, and directories with everything below them.
+    /// Adds to the paths of earlier calls.
+    pub fn skip_paths(&mut self, paths: impl IntoIterator<Item = PathBuf>) {
+        self.skip_paths.extend(paths);
+    }
+
+    /// Don't descend into other filesystems mounted below a scanned root.
+    pub fn set_one_filesystem(&mut self, on: bool) {
+        self.one_filesystem = on;
+    }
+
+    /// Rate-limit the bytes read while hashing to `bytes_per_sec`.
+    pub fn set_throttle(&mut self, bytes_per_sec: Option<u64>) {
+        self.throttle = bytes_per_sec.map(Throttle::new);
+    }
+
+    /// Hash files of at least `bytes` through a memory map (0 = always stream).
+    pub fn set_mmap_threshold(&mut self, bytes: u64) {
+        self.mmap_threshold = bytes;
+    }
+
+    /// Maximum Hamming distance for `DedupeMethod::Perceptual` groups.
+    pub fn set_perceptual_distance(&mut self, distance: u32) {
+        self.perceptual_distance = distance;
+    }
+
+    /// Take full hashes of files unchanged since an earlier run from `cache`, and add the
+    /// ones computed now; `save_hash_cache` writes it back.
+    pub fn set_hash_cache(&mut self, cache: Option<HashCache>) {
+        self.hash_cache = cache;
+    }
+
+    pub fn save_hash_cache(&self) -> Result<()> {
+        self.hash_cache.as_ref().map_or(Ok(()), HashCache::save)
+    }
+
+    /// Hash every file fully with blake3 instead of the quick xxh3 pre-pass.
+    pub fn set_full_hash(&mut self, on: bool) {
+        self.full_hash = on;
+    }
+
+    /// Name and size groups only count as duplicates where their members also hash the
+    /// same; only files in such a group are hashed. No effect once `Hash` or `Perceptual`
+    /// is among the methods.
+    pub fn set_confirm_with_hash(&mut self, on: bool) {
+        self.confirm_with_hash = on;
+    }
+
+    /// Whether name/size candidates still need their hashes compared.
+    fn confirms_with_hash(&self) -> bool {
+        self.confirm_with_hash && !self.methods.iter().any(|m| matches!(m, DedupeMethod::Hash | DedupeMethod::Perceptual))
+    }
+
+    /// Ask which file to keep for every group. Only enabled when stdin is a terminal;
+    /// returns whether interactive mode is active.
+    pub fn set_interactive(&mut self, on: bool) -> bool {
+        self.interactive = on && std::io::stdin().is_terminal();
+        self.interactive
     }
 
     pub fn scan(&mut self, root: &Path) -> Result<()> {
-        // Collect files recursively
-        for entry in walkdir::WalkDir::new(root).follow_links(false) {
+        self.scan_all(&[root])
+    }
+
+    /// Scan several trees into one plan, so duplicates are found across them.
+    pub fn scan_all<P: AsRef<Path>>(&mut self, roots: &[P]) -> Result<()> {
+        for root in roots {
+            self.collect(root.as_ref(), root.as_ref());
+        }
+        self.hash_collected();
+        Ok(())
+    }
+
+    /// Scan only `paths`: files, and directories with everything below them. `roots` place
+    /// each path in its tree, for the category scope and relative keep prefixes.
+    pub fn scan_paths<P: AsRef<Path>>(&mut self, roots: &[P], paths: &[PathBuf]) -> Result<()> {
+        for path in paths {
+            let root = roots.iter().map(AsRef::as_ref)
+                .filter(|r| path.starts_with(r))
+                .max_by_key(|r| r.components().count())
+                .or(path.parent())
+                .unwrap_or(path);
+            self.collect(root, path);
+        }
+        // The same file reached twice must not become its own duplicate
+        let mut seen = HashSet::new();
+        self.files.retain(|f| seen.insert(f.path.clone()));
+        self.hash_collected();
+        Ok(())
+    }
+
+    fn hash_collected(&mut self) {
+        // If hash is required, compute in parallel
+        if self.methods.contains(&DedupeMethod::Hash) {
+            self.hash_files(|_| true);
+        } else if self.confirms_with_hash() {
+            let candidates: HashSet<PathBuf> = self.key_groups_by(false).into_iter().flatten().map(|f| f.path.clone()).collect();
+            self.hash_files(|f| candidates.contains(&f.path));
+        }
+        if self.methods.contains(&DedupeMethod::Perceptual) {
+            self.files.par_iter_mut()
+                .filter(|f| is_image(&f.path))
+                .for_each(|f| f.phash = perceptual_hash(&f.path));
+        }
+    }
+
+    /// Record every regular file at or below `start`, a path in the tree of `root`.
+    fn collect(&mut self, root: &Path, start: &Path) {
+        if self.skip_paths.iter().any(|p| start.starts_with(p)) {
+            return;
+        }
+        let marker = self.protect_marker.clone();
+        let skip = self.skip_suffixes.clone();
+        let skip_paths = &self.skip_paths;
+        let include_hidden = self.include_hidden;
+        let walker = walkdir::WalkDir::new(start).follow_links(false).same_file_system(self.one_filesystem).into_iter()
+            .filter_entry(|e| {
+                let name = e.file_name().to_string_lossy().to_lowercase();
+                let incomplete = e.depth() > 0 && skip.iter().any(|s| name.ends_with(s.as_str()));
+                let hidden = e.depth() > 0 && !include_hidden && is_hidden_name(e.file_name());
+                let protected = e.file_type().is_dir() && marker.as_ref().is_some_and(|m| e.path().join(m).exists());
+                !incomplete && !protected && !hidden && !skip_paths.contains(e.path())
+            });
+        for entry in walker {
             let entry = match entry {
                 Ok(e) => e,
                 Err(_) => continue,
             };
-            if entry.file_type().is_file() {
+            if entry.file_type().is_file() && extension_allowed(entry.path(), &self.only_extensions, &self.ignore_extensions) {
                 let path = entry.path().to_path_buf();
-                let name = entry.file_name().to_string_lossy().to_string();
-                let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
-                self.files.push(FileInfo { path, name, size, hash: None });
+                let name = entry.file_name().to_os_string();
+                let md = entry.metadata().ok();
+                let size = md.as_ref().map(|m| m.len()).unwrap_or(0);
+                let measured = md.as_ref().map(|m| self.size_metric.of(m)).unwrap_or(0);
+                let mtime_ns = md.as_ref().map(mtime_ns).unwrap_or(0);
+                if self.max_size.is_some_and(|max| measured > max) {
+                    continue;
+                }
+                let rel = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+                let file_id = md.as_ref().and_then(file_id);
+                self.files.push(FileInfo { path, name, rel, size, mtime_ns, quick: None, hash: None, phash: None, file_id });
             }
         }
-        // If hash is required, compute in parallel
-        if self.methods.contains(&DedupeMethod::Hash) {
-            self.files.par_iter_mut().for_each(|f| { let _ = f.compute_hash(); });
+    }
+
+    /// Hash the files `wanted` picks, in stages that each only read possible duplicates:
+    /// files are bucketed by size and only sizes shared by two or more are read, with the
+    /// quick hash first and the full blake3 for quick-hash collisions (or straight away
+    /// with `full_hash`). Hashes found in the hash cache are not taken again; a file
+    /// sharing its size with one that only has a cached full hash is fully hashed, as it
+    /// could be its copy.
+    fn hash_files(&mut self, wanted: impl Fn(&FileInfo) -> bool + Sync) {
+        let (throttle, mmap) = (self.throttle.as_ref(), self.mmap_threshold);
+        let mut by_size: HashMap<u64, usize> = HashMap::new();
+        for f in self.files.iter().filter(|f| wanted(f)) {
+            *by_size.entry(f.size).or_default() += 1;
         }
+        self.stats.files += by_size.values().sum::<usize>();
+        self.stats.size_buckets += by_size.len();
+        self.stats.shared_size_buckets += by_size.values().filter(|&&n| n > 1).count();
+        let shares_size = |f: &FileInfo| wanted(f) && by_size[&f.size] > 1;
+        if let Some(cache) = &self.hash_cache {
+            for f in self.files.iter_mut().filter(|f| f.quick.is_none() && f.hash.is_none() && shares_size(f)) {
+                (f.quick, f.hash) = cache.get(&f.path, f.size, f.mtime_ns);
+                self.stats.cached_hashes += usize::from(f.quick.is_some() || f.hash.is_some());
+            }
+        }
+        // Without a quick hash these can only be matched by the full one
+        let full_only: HashSet<u64> = self.files.iter().filter(|f| f.hash.is_some() && f.quick.is_none()).map(|f| f.size).collect();
+        let unhashed = |f: &FileInfo| f.hash.is_none() && shares_size(f);
+        let mut collisions: HashMap<(u64, u64), usize> = HashMap::new();
+        if !self.full_hash {
+            let read: Vec<u64> = self.files.par_iter_mut()
+                .filter(|f| unhashed(f) && f.quick.is_none() && !full_only.contains(&f.size))
+                .filter_map(|f| f.compute_quick_hash(throttle).ok().map(|_| f.size.min(2 * QUICK_CHUNK)))
+                .collect();
+            self.stats.quick_hashes += read.len();
+            self.stats.bytes_read += read.iter().sum::<u64>();
+            for f in self.files.iter().filter(|f| shares_size(f)) {
+                if let Some(q) = f.quick {
+                    *collisions.entry((f.size, q)).or_default() += 1;
+                }
+            }
+        }
+        let full_hash = self.full_hash;
+        let needs_full = |f: &FileInfo| {
+            full_hash || full_only.contains(&f.size) || f.quick.is_some_and(|q| collisions.get(&(f.size, q)).is_some_and(|&n| n > 1))
+        };
+        let read: Vec<u64> = self.files.par_iter_mut()
+            .filter(|f| unhashed(f) && needs_full(f))
+            .filter_map(|f| f.compute_hash(throttle, mmap).ok().map(|_| f.size))
+            .collect();
+        self.stats.full_hashes += read.len();
+        self.stats.bytes_read += read.iter().sum::<u64>();
+        if let Some(cache) = self.hash_cache.as_mut() {
+            for f in self.files.iter().filter(|f| f.quick.is_some() || f.hash.is_some()) {
+                cache.insert(&f.path, f.size, f.mtime_ns, f.quick, f.hash);
+            }
+        }
+    }
+
+    /// Counts from the hashing stages of the scans so far.
+    pub fn stats(&self) -> DedupeStats {
+        self.stats
+    }
+
+    /// Enumerate duplicate groups without acting on them.
+    pub fn report(&self) -> Vec<DupGroup> {
+        self.groups().into_iter().map(|g| {
+            let (keep, rest) = g.split_first().unwrap();
+            DupGroup {
+                keep: keep.path.clone(),
+                size: keep.size,
+                duplicates: rest.iter().map(|d| DupEntry { path: d.path.clone(), size: d.size }).collect(),
+            }
+        }).collect()
+    }
+
+    /// Write every group as CSV, one row per file: `group,path,size,hash,kept`. The hash is
+    /// the blake3 hex digest where one was computed (hash method), else empty.
+    pub fn write_report(&self, path: &Path) -> Result<()> {
+        let mut out = std::io::BufWriter::new(fs::File::create(path).with_context(|| format!("create {}", path.display()))?);
+        writeln!(out, "group,path,size,hash,kept")?;
+        for (i, group) in self.groups().into_iter().enumerate() {
+            for (j, f) in group.iter().enumerate() {
+                let hash = f.hash.map(|h| blake3::Hash::from(h).to_hex().to_string()).unwrap_or_default();
+                writeln!(out, "{},{},{},{},{}", i + 1, csv_field(&f.path.to_string_lossy()), f.size, hash, j == 0)?;
+            }
+        }
+        out.flush()?;
         Ok(())
     }
 
-    pub fn apply(&self, mode: DedupeMode, engine: &mut ActionEngine) -> Result<()> {
+    /// Deletions that would remove every duplicate (keeping the first file of each group).
+    pub fn duplicate_deletes(&self) -> Vec<Action> {
+        self.groups().into_iter()
+            .flat_map(|g| g.into_iter().skip(1))
+            .map(|dup| Action::Delete(dup.path.clone(), "duplicate file".into()))
+            .collect()
+    }
+
+    /// Act on every group (keeping the first file); returns how many duplicates were handled.
+    pub fn apply(&self, mode: DedupeMode, engine: &mut ActionEngine) -> Result<usize> {
+        let groups = self.groups();
+        // A protected survivor is not up for choice
+        let ask: Vec<bool> = groups.iter().map(|g| self.interactive && !self.is_protected(g[0])).collect();
+        let groups = groups.into_iter()
+            .map(|g| g.into_iter().map(|f| DupEntry { path: f.path.clone(), size: f.size }).collect())
+            .collect();
+        apply_groups(groups, mode, |i| ask[i], self.inherit_mtime, self.cross_device_symlink, self.preview_limit, engine)
+    }
+
+    fn is_protected(&self, fi: &FileInfo) -> bool {
+        self.protect.as_ref().is_some_and(|p| p.is_match(&fi.path) || p.is_match(&fi.rel))
+    }
+
+    /// Members that are hardlinks of an earlier member of their group, grouped under that
+    /// member. Their data is already shared, so they are never acted on.
+    pub fn already_linked(&self) -> Vec<DupGroup> {
+        self.split_linked().1.into_iter().map(|(keep, links)| DupGroup {
+            keep: keep.path.clone(),
+            size: keep.size,
+            duplicates: links.iter().map(|d| DupEntry { path: d.path.clone(), size: d.size }).collect(),
+        }).collect()
+    }
+
+    /// Duplicate groups with hardlinks of an earlier member left out.
+    fn groups(&self) -> Vec<Vec<&FileInfo>> {
+        self.split_linked().0
+    }
+
+    /// Split every group into its distinct files (kept as a group while more than one is
+    /// left) and the hardlinks of those files.
+    fn split_linked(&self) -> (Vec<Vec<&FileInfo>>, Vec<Links<'_>>) {
+        let mut groups = Vec::new();
+        let mut linked = Vec::new();
+        for group in self.key_groups() {
+            let mut distinct: Vec<&FileInfo> = Vec::new();
+            let mut links: Vec<Links> = Vec::new();
+            for fi in group {
+                let first = fi.file_id.and_then(|id| distinct.iter().find(|d| d.file_id == Some(id)).copied());
+                match first {
+                    Some(first) => match links.iter_mut().find(|(f, _)| std::ptr::eq(*f, first)) {
+                        Some((_, l)) => l.push(fi),
+                        None => links.push((first, vec![fi])),
+                    },
+                    None => distinct.push(fi),
+                }
+            }
+            if distinct.len() > 1 {
+                groups.push(distinct);
+            }
+            linked.extend(links);
+        }
+        (groups, linked)
+    }
+
+    /// Duplicate groups (more than one member) keyed by the selected method(s).
+    /// Groups come out in key order with members sorted by path, so the survivor and
+    /// the action order do not depend on walk or hash-map order.
+    fn key_groups(&self) -> Vec<Vec<&FileInfo>> {
+        self.key_groups_by(self.confirms_with_hash())
+    }
+
+    /// `key_groups`, with the full hash added to the key when `confirm` is set.
+    fn key_groups_by(&self, confirm: bool) -> Vec<Vec<&FileInfo>> {
         // Group by selected key(s)
-        let mut groups: HashMap<String, Vec<&FileInfo>> = HashMap::new();
+        let perceptual = self.methods.contains(&DedupeMethod::Perceptual);
+        let mut groups: BTreeMap<Vec<KeyPart>, Vec<&FileInfo>> = BTreeMap::new();
         for fi in &self.files {
-            let mut parts: Vec<String> = vec![];
+            // Perceptual grouping only considers images that could be hashed
+            if perceptual && fi.phash.is_none() { continue; }
+            let mut key: Vec<KeyPart> = vec![];
+            match self.scope {
+                DedupeScope::Global => {}
+                DedupeScope::WithinCategory => key.push(KeyPart::Category(fi.category())),
+                DedupeScope::SameDirectory => key.push(KeyPart::Dir(fi.path.parent())),
+                DedupeScope::WithinDepth(depth) => key.push(KeyPart::Subtree(fi.subtree(depth))),
+            }
             for m in &self.methods {
                 match m {
-                    DedupeMethod::Name => parts.push(format!("N:{}", fi.name)),
-                    DedupeMethod::Size => parts.push(format!("S:{}", fi.size)),
-                    DedupeMethod::Hash => {
-                        let hex = fi.hash.map(|h| hex::encode(h)).unwrap_or_else(|| "NOHASH".into());
-                        parts.push(format!("H:{}", hex));
-                    }
+                    DedupeMethod::Name => key.push(KeyPart::Name(name_key(&fi.name, self.name_mode))),
+                    DedupeMethod::Size => key.push(KeyPart::Size(fi.size)),
+                    DedupeMethod::Hash => match fi.hash {
+                        Some(h) => key.push(KeyPart::Hash(h)),
+                        // Unique by quick hash, or unreadable: never a duplicate
+                        None => key.push(KeyPart::Unique(&fi.path)),
+                    },
+                    // Not a key: clustered by distance below
+                    DedupeMethod::Perceptual => {}
                 }
             }
-            let key = parts.join("|");
+            if confirm {
+                key.push(fi.hash.map_or(KeyPart::Unique(&fi.path), KeyPart::Hash));
+            }
             groups.entry(key).or_default().push(fi);
         }
 
-        // For each group with >1, keep first, remove others
-        for (_k, vecf) in groups.into_iter() {
-            if vecf.len() <= 1 { continue; }
-            // Keep the first file, operate on the rest
-            let (keep, rest) = vecf.split_first().unwrap();
-            for dup in rest {
-                match mode {
-                    DedupeMode::Delete => {
-                        // current behavior: just delete duplicates
-                        engine.execute(&Action::Delete(dup.path.clone(), "duplicate file".into()))?;
-                    }
-                    DedupeMode::Hardlink => {
-                        // replace duplicate with a hardlink to the kept file
-                        engine.execute(&Action::Delete(dup.path.clone(), "duplicate file (to hardlink)".into()))?;
-                        if engine.apply_mode() {
-                            let _ = std::fs::hard_link(&keep.path, &dup.path);
-                        }
+        let groups = groups.into_values().filter(|g| g.len() > 1).map(|mut g| {
+            g.sort_by(|a, b| a.path.cmp(&b.path));
+            g
+        });
+        let mut groups: Vec<Vec<&FileInfo>> = if perceptual {
+            groups.flat_map(|g| cluster_by_distance(g, self.perceptual_distance)).collect()
+        } else {
+            groups.collect()
+        };
+        // The first member survives: move the first one under the keep prefix to the front
+        if let Some(prefix) = &self.keep_prefix {
+            for g in &mut groups {
+                if let Some(i) = g.iter().position(|f| f.is_under(prefix)) {
+                    let keep = g.remove(i);
+                    g.insert(0, keep);
+                }
+            }
+        }
+        // Protected files outrank the keep prefix; only the first of them stays in the group
+        if self.protect.is_some() {
+            for g in &mut groups {
+                let (protected, rest): (Vec<&FileInfo>, Vec<&FileInfo>) = g.iter().partition(|f| self.is_protected(f));
+                if let Some(first) = protected.first() {
+                    *g = std::iter::once(*first).chain(rest).collect();
+                }
+            }
+            groups.retain(|g| g.len() > 1);
+        }
+        groups
+    }
+}
+
+/// The `--dedup-protect` patterns as one set; `None` when there are none.
+pub fn protect_set(patterns: &[String]) -> Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+        set.add(Glob::new(pattern).with_context(|| format!("invalid --dedup-protect pattern `{pattern}`"))?);
+    }
+    Ok(Some(set.build()?))
+}
+
+/// Clone `keep` next to `dup` and rename the clone over it, so `dup` is never missing.
+fn replace_with_reflink(keep: &Path, dup: &Path) -> std::io::Result<()> {
+    replace_via_temp(dup, "reflink", |tmp| reflink_copy::reflink(keep, tmp), |_| true)
+}
+
+/// Replace `dup` with a hardlink to `keep`, logged as its deletion. A link that can't be
+/// made (other filesystem, no hardlink support) leaves the duplicate in place.
+fn hardlink_over(keep: &Path, dup: &Path, engine: &mut ActionEngine) {
+    let res = engine.replace_file(dup, "duplicate file (to hardlink)", || {
+        replace_via_temp(dup, "hardlink", |tmp| fs::hard_link(keep, tmp), |tmp| {
+            let id = |p: &Path| fs::metadata(p).ok().as_ref().and_then(file_id);
+            // Without file ids (non-Unix) the successful link call has to do
+            match (id(tmp), id(keep)) {
+                (Some(a), Some(b)) => a == b,
+                _ => true,
+            }
+        })
+    });
+    match res {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+            engine.log(format!("WARN can't hardlink {} to {} across filesystems; duplicate kept", dup.display(), keep.display()));
+        }
+        Err(err) => engine.log(format!("ERROR hardlinking {}: {}; duplicate kept", dup.display(), err)),
+    }
+}
+
+/// Replace `dup` with a symlink to `keep`, logged as its deletion.
+fn symlink_over(keep: &Path, dup: &Path, engine: &mut ActionEngine) {
+    let res = engine.replace_file(dup, "duplicate file (to symlink)", || {
+        replace_via_temp(dup, "symlink", |tmp| make_symlink(keep, tmp), |tmp| {
+            fs::read_link(tmp).is_ok_and(|t| t == keep)
+        })
+    });
+    if let Err(err) = res {
+        engine.log(format!("ERROR symlinking {}: {}; duplicate kept", dup.display(), err));
+    }
+}
+
+/// Filesystem `path` is on (`st_dev`), where known.
+fn device_of(path: &Path) -> Option<u64> {
+    fs::symlink_metadata(path).ok().as_ref().and_then(device_id)
+}
+
+/// Build the replacement next to `dup` under a temporary name with `make`, check it with
+/// `verify`, then rename it over `dup`. `dup` stays intact until that final atomic rename.
+fn replace_via_temp(
+    dup: &Path,
+    kind: &str,
+    make: impl FnOnce(&Path) -> std::io::Result<()>,
+    verify: impl FnOnce(&Path) -> bool,
+) -> std::io::Result<()> {
+    let mut tmp_name = dup.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".organizer-{kind}"));
+    let tmp = dup.with_file_name(tmp_name);
+    make(&tmp)?;
+    if !verify(&tmp) {
+        let _ = fs::remove_file(&tmp);
+        return Err(std::io::Error::other("replacement did not verify"));
+    }
+    fs::rename(&tmp, dup).inspect_err(|_| { let _ = fs::remove_file(&tmp); })
+}
+
+/// One component of a grouping key. Names compare as raw `OsStr`, so distinct
+/// non-UTF-8 names never collide through lossy conversion.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum KeyPart<'a> {
+    Category(Option<&'a OsStr>),
+    Dir(Option<&'a Path>),
+    Subtree(Vec<&'a OsStr>),
+    Name(Cow<'a, OsStr>),
+    Size(u64),
+    Hash([u8; 32]),
+    Unique(&'a Path),
+}
+
+/// Name as compared under `mode`. Non-UTF-8 names are only matched exactly (or by stem).
+fn name_key(name: &OsStr, mode: DedupeNameMode) -> Cow<'_, OsStr> {
+    match mode {
+        DedupeNameMode::Exact => Cow::Borrowed(name),
+        DedupeNameMode::CaseInsensitive => match name.to_str() {
+            Some(s) => Cow::Owned(s.to_lowercase().into()),
+            None => Cow::Borrowed(name),
+        },
+        DedupeNameMode::Stem => Cow::Borrowed(Path::new(name).file_stem().unwrap_or(name)),
+    }
+}
+
+/// Split a group into clusters whose perceptual hashes are within `max` bits
+/// of some other member (single-linkage). Singletons are dropped.
+fn cluster_by_distance(group: Vec<&FileInfo>, max: u32) -> Vec<Vec<&FileInfo>> {
+    let mut parent: Vec<usize> = (0..group.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for i in 0..group.len() {
+        for j in i + 1..group.len() {
+            let (Some(a), Some(b)) = (&group[i].phash, &group[j].phash) else { continue };
+            if hamming(a, b) <= max {
+                let (ri, rj) = (root(&mut parent, i), root(&mut parent, j));
+                parent[rj] = ri;
+            }
+        }
+    }
+    let mut clusters: HashMap<usize, Vec<&FileInfo>> = HashMap::new();
+    for (i, fi) in group.into_iter().enumerate() {
+        clusters.entry(root(&mut parent, i)).or_default().push(fi);
+    }
+    let mut clusters: Vec<_> = clusters.into_values().filter(|c| c.len() > 1).collect();
+    // Members keep the group's path order; order clusters by their first member
+    clusters.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+    clusters
+}
+
+fn hamming(a: &[u8], b: &[u8]) -> u32 {
+    a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum()
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str())
+        .is_some_and(|e| IMAGE_EXTS.iter().any(|i| e.eq_ignore_ascii_case(i)))
+}
+
+#[cfg(feature = "perceptual")]
+fn perceptual_hash(path: &Path) -> Option<Vec<u8>> {
+    use img_hash::{HashAlg, HasherConfig};
+    let img = img_hash::image::open(path).ok()?;
+    let hasher = HasherConfig::new().hash_alg(HashAlg::Gradient).to_hasher();
+    Some(hasher.hash_image(&img).as_bytes().to_vec())
+}
+
+#[cfg(not(feature = "perceptual"))]
+fn perceptual_hash(_path: &Path) -> Option<Vec<u8>> {
+    None
+}
+
+/// Act on previously reported groups (e.g. from a saved plan), keeping each group's `keep`.
+pub fn apply_saved(groups: &[DupGroup], mode: DedupeMode, inherit_mtime: bool, cross_device_symlink: bool, preview_limit: Option<usize>, engine: &mut ActionEngine) -> Result<usize> {
+    let groups = groups.iter()
+        .map(|g| std::iter::once(DupEntry { path: g.keep.clone(), size: g.size }).chain(g.duplicates.iter().cloned()).collect())
+        .collect();
+    apply_groups(groups, mode, |_| false, inherit_mtime, cross_device_symlink, preview_limit, engine)
+}
+
+/// `interactive` tells, by group index, whether to ask which member to keep. Groups past
+/// `preview_limit` are handled without printing their lines (see `set_preview_limit`).
+fn apply_groups(groups: Vec<Vec<DupEntry>>, mode: DedupeMode, interactive: impl Fn(usize) -> bool, inherit_mtime: bool, cross_device_symlink: bool, preview_limit: Option<usize>, engine: &mut ActionEngine) -> Result<usize> {
+    let shown = preview_limit.unwrap_or(usize::MAX);
+    let hidden: Vec<usize> = groups.iter().skip(shown).map(|g| g.len() - 1).collect();
+    let quiet = engine.quiet_mode();
+    let handled = handle_groups(groups, mode, interactive, inherit_mtime, cross_device_symlink, shown, engine);
+    // The hidden groups ran quietly, however they ended
+    engine.set_quiet(quiet);
+    if !hidden.is_empty() {
+        println!("# ... and {} more duplicate groups ({} duplicates) not shown", hidden.len(), hidden.iter().sum::<usize>());
+    }
+    handled
+}
+
+fn handle_groups(groups: Vec<Vec<DupEntry>>, mode: DedupeMode, interactive: impl Fn(usize) -> bool, inherit_mtime: bool, cross_device_symlink: bool, shown: usize, engine: &mut ActionEngine) -> Result<usize> {
+    let mut handled = 0;
+    // For each group with >1, keep first, remove others
+    for (i, mut vecf) in groups.into_iter().enumerate() {
+        if i == shown {
+            engine.set_quiet(true);
+        }
+        if interactive(i) {
+            match prompt_keep(i + 1, &vecf)? {
+                Some(idx) => { let chosen = vecf.remove(idx); vecf.insert(0, chosen); }
+                None => {
+                    engine.log(format!("SKIP duplicate group {} ({})", i + 1, vecf[0].path.display()));
+                    continue;
+                }
+            }
+        }
+        // Keep the first file, operate on the rest
+        let (keep, rest) = vecf.split_first().unwrap();
+        // Measured before the duplicates are gone (or replaced by links)
+        let oldest = if inherit_mtime { oldest_mtime(&vecf) } else { None };
+        let keep_dev = device_of(&keep.path);
+        // Hardlinks stay within a filesystem: the first copy on each other one is linked to instead
+        let mut local_keeps: HashMap<u64, &Path> = HashMap::new();
+        for dup in rest {
+            // Checked between duplicates only: a replacement in progress always completes
+            if stop_requested() {
+                return Ok(handled);
+            }
+            if mode == DedupeMode::Hardlink {
+                if let Some(dev) = device_of(&dup.path).filter(|d| keep_dev.is_some_and(|k| k != *d)) {
+                    if let Some(local) = local_keeps.get(&dev) {
+                        handled += 1;
+                        hardlink_over(local, &dup.path, engine);
+                    } else if cross_device_symlink {
+                        local_keeps.insert(dev, &dup.path);
+                        handled += 1;
+                        symlink_over(&keep.path, &dup.path, engine);
+                    } else {
+                        local_keeps.insert(dev, &dup.path);
+                        engine.log(format!("SKIP {} (on another filesystem than {}; kept for its own duplicates there)", dup.path.display(), keep.path.display()));
                     }
-                    DedupeMode::Symlink => {
-                        // replace duplicate with a symlink to the kept file
-                        engine.execute(&Action::Delete(dup.path.clone(), "duplicate file (to symlink)".into()))?;
-                        if engine.apply_mode() {
-                            #[cfg(unix)]
-                            { let _ = std::os::unix::fs::symlink(&keep.path, &dup.path); }
+                    continue;
+                }
+            }
+            handled += 1;
+            match mode {
+                DedupeMode::Delete => {
+                    // current behavior: just delete duplicates
+                    engine.execute(&Action::Delete(dup.path.clone(), "duplicate file".into()))?;
+                }
+                DedupeMode::Hardlink => hardlink_over(&keep.path, &dup.path, engine),
+                DedupeMode::Reflink => {
+                    engine.log(format!("REFLINK {} -> {}", dup.path.display(), keep.path.display()));
+                    if engine.apply_mode() {
+                        if let Err(err) = replace_with_reflink(&keep.path, &dup.path) {
+                            engine.log(format!("WARN reflink not supported for {} ({}); using a hardlink", dup.path.display(), err));
+                            hardlink_over(&keep.path, &dup.path, engine);
                         }
                     }
                 }
+                DedupeMode::Symlink => symlink_over(&keep.path, &dup.path, engine),
             }
         }
-        Ok(())
+        if let Some(oldest) = oldest {
+            inherit_oldest_mtime(&keep.path, oldest, engine);
+        }
+    }
+    Ok(handled)
+}
+
+/// Quote a CSV field when it holds a separator, quote or line break.
+fn csv_field(s: &str) -> Cow<'_, str> {
+    if s.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", s.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(s)
     }
 }
 
-// local hex encode to avoid extra deps
-mod hex {
-    pub fn encode(bytes: [u8;32]) -> String {
-        let mut s = String::with_capacity(64);
-        for b in bytes {
-            s.push(hex_char(b >> 4));
-            s.push(hex_char(b & 0x0f));
+/// Earliest mtime among the group's files.
+fn oldest_mtime(group: &[DupEntry]) -> Option<SystemTime> {
+    group.iter().filter_map(|f| fs::metadata(&f.path).and_then(|m| m.modified()).ok()).min()
+}
+
+/// Set `keep`'s mtime to `oldest` when that is earlier than its own.
+fn inherit_oldest_mtime(keep: &Path, oldest: SystemTime, engine: &mut ActionEngine) {
+    if fs::metadata(keep).and_then(|m| m.modified()).is_ok_and(|t| t <= oldest) {
+        return;
+    }
+    let fmt = time::macros::format_description!("[year]-[month]-[day] [hour]:[minute]");
+    let date = time::OffsetDateTime::from(oldest).format(fmt).unwrap_or_default();
+    engine.log(format!("MTIME {} -> {} (oldest in its group)", keep.display(), date));
+    if engine.apply_mode() {
+        if let Err(err) = filetime::set_file_mtime(keep, filetime::FileTime::from_system_time(oldest)) {
+            engine.log(format!("ERROR setting mtime of {}: {}", keep.display(), err));
         }
-        s
     }
-    #[inline]
-    fn hex_char(n: u8) -> char {
-        match n {
-            0..=9 => (b'0' + n) as char,
-            10..=15 => (b'a' + (n - 10)) as char,
-            _ => '?',
+}
+
+/// Show a group and ask which member to keep. `None` means skip the group.
+fn prompt_keep(group_no: usize, group: &[DupEntry]) -> Result<Option<usize>> {
+    let fmt = time::macros::format_description!("[year]-[month]-[day] [hour]:[minute]");
+    println!("Duplicate group {} ({} files):", group_no, group.len());
+    for (i, f) in group.iter().enumerate() {
+        let mtime = fs::metadata(&f.path).and_then(|m| m.modified()).ok()
+            .and_then(|t| time::OffsetDateTime::from(t).format(fmt).ok())
+            .unwrap_or_else(|| "?".into());
+        println!("  [{}] {}  {}  {}", i + 1, f.path.display(), human_size(f.size), mtime);
+    }
+    loop {
+        print!("Keep which? [1-{}, s=skip, Enter=1]: ", group.len());
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            return Ok(Some(0)); // EOF: default keep-strategy
+        }
+        match line.trim() {
+            "" => return Ok(Some(0)),
+            "s" | "S" => return Ok(None),
+            n => match n.parse::<usize>() {
+                Ok(k) if (1..=group.len()).contains(&k) => return Ok(Some(k - 1)),
+                _ => println!("Please enter a number between 1 and {}, or s.", group.len()),
+            },
+        }
+    }
+}
+
+fn read_full(f: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match f.read(&mut buf[n..])? {
+            0 => break,
+            k => n += k,
         }
     }
+    Ok(n)
 }
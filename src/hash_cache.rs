@@ -0,0 +1,56 @@
+use crate::utils::{read_jsonl, write_jsonl, FileStamp};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Hashes from earlier dedupe runs (the quick pre-pass hash, the full one, or both), by
+/// path, trusted while the file keeps the size and mtime it had when hashed. Stored as
+/// JSON lines like the journal.
+#[derive(Debug, Default)]
+pub struct HashCache {
+    path: PathBuf,
+    entries: HashMap<PathBuf, Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    file: FileStamp,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quick: Option<u64>,
+    /// blake3, hex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+}
+
+impl HashCache {
+    pub fn load(path: &Path) -> Result<Self> {
+        let entries = read_jsonl::<Entry>(path, "hash cache")?.into_iter()
+            .map(|e| (e.file.path.clone(), e))
+            .collect();
+        Ok(Self { path: path.to_path_buf(), entries })
+    }
+
+    /// The quick and full hash recorded for `path`, if they were taken at this size and mtime.
+    pub fn get(&self, path: &Path, size: u64, mtime_ns: u64) -> (Option<u64>, Option<[u8; 32]>) {
+        let Some(e) = self.entries.get(path).filter(|e| e.file.size == size && e.file.mtime_ns == mtime_ns) else { return (None, None) };
+        let hash = e.hash.as_deref().and_then(|h| blake3::Hash::from_hex(h).ok()).map(|h| *h.as_bytes());
+        (e.quick, hash)
+    }
+
+    /// Remember the hashes of `path` as it is now (size and mtime).
+    pub fn insert(&mut self, path: &Path, size: u64, mtime_ns: u64, quick: Option<u64>, hash: Option<[u8; 32]>) {
+        let hash = hash.map(|h| blake3::Hash::from(h).to_hex().to_string());
+        let file = FileStamp { path: path.to_path_buf(), size, mtime_ns };
+        self.entries.insert(path.to_path_buf(), Entry { file, quick, hash });
+    }
+
+    /// Rewrite the cache with the entries whose file is still there unchanged; files that
+    /// were deleted, moved or modified since drop out.
+    pub fn save(&self) -> Result<()> {
+        let mut entries: Vec<&Entry> = self.entries.values().filter(|e| e.file.is_current()).collect();
+        entries.sort_by(|a, b| a.file.path.cmp(&b.file.path));
+        write_jsonl(&self.path, "hash cache", entries)
+    }
+}
@@ -0,0 +1,41 @@
+use crate::utils::{read_jsonl, write_jsonl, FileStamp};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Files seen by earlier runs, identified by path, size and mtime. Stored as JSON lines;
+/// a missing journal is simply empty.
+#[derive(Debug, Default)]
+pub struct Journal {
+    path: PathBuf,
+    seen: HashSet<FileStamp>,
+    unchanged: usize,
+}
+
+impl Journal {
+    pub fn load(path: &Path) -> Result<Self> {
+        let seen = read_jsonl(path, "journal")?.into_iter().collect();
+        Ok(Self { path: path.to_path_buf(), seen, unchanged: 0 })
+    }
+
+    /// Whether `path` was seen before with this size and mtime. Unseen files are remembered.
+    pub fn check(&mut self, path: &Path, md: &fs::Metadata) -> bool {
+        let known = !self.seen.insert(FileStamp::of(path, md));
+        self.unchanged += usize::from(known);
+        known
+    }
+
+    /// Files skipped by `check` so far.
+    pub fn unchanged(&self) -> usize {
+        self.unchanged
+    }
+
+    /// Rewrite the journal with every remembered file that is still there unchanged;
+    /// files moved or deleted by this run drop out.
+    pub fn save(&self) -> Result<()> {
+        let mut entries: Vec<&FileStamp> = self.seen.iter().filter(|e| e.is_current()).collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        write_jsonl(&self.path, "journal", entries)
+    }
+}
@@ -0,0 +1,1402 @@
+//! Organize, deduplicate, and clean large data trees.
+//!
+//! The `organizer` binary is a thin CLI over [`run`]. Embedders can drive the same pipeline
+//! with a [`Config`], only plan it with [`plan`], or use [`Categorizer`], [`DedupePlan`] and
+//! [`ActionEngine`] directly.
+
+pub mod actions;
+pub mod categorize;
+pub mod config;
+pub mod dedupe;
+pub mod hash_cache;
+pub mod journal;
+pub mod quarantine;
+pub mod saved_plan;
+pub mod utils;
+pub mod verify;
+pub mod watch;
+
+pub use crate::actions::{Action, ActionEngine, DedupeDirMode, DirConflict, LogFormat};
+pub use crate::categorize::{Categorizer, Category};
+pub use crate::config::Settings;
+pub use crate::dedupe::{DedupeMethod, DedupeMode, DedupeNameMode, DedupePlan, DedupeScope, DedupeStats, DupGroup};
+
+use crate::dedupe::{DEFAULT_MMAP_THRESHOLD, DEFAULT_PERCEPTUAL_DISTANCE};
+use crate::hash_cache::HashCache;
+use crate::journal::Journal;
+use crate::quarantine::Quarantine;
+use crate::saved_plan::SavedPlan;
+use crate::utils::{alpha_bucket, device_id, expand_date_template, extension_allowed, human_size, is_broken_symlink, is_hidden_name, is_pattern_match, readable_display, NameNormalization, SizeMetric};
+use anyhow::{bail, Result};
+use globset::GlobSet;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use walkdir::WalkDir;
+
+/// Output format for reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Options for one organizer run. `Config::new` starts from the CLI defaults.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Trees to organize; all of them feed one plan and one dedupe scan
+    pub roots: Vec<PathBuf>,
+    /// Organize just these paths (e.g. from `find`) instead of walking the roots; each must lie
+    /// below one of them. Dedupe then only looks at these files, wherever they ended up
+    pub paths: Option<Vec<PathBuf>>,
+    pub settings: Settings,
+    pub apply: bool,
+    /// Create categories under this folder instead of directly in each root
+    /// (relative to the first root unless absolute)
+    pub under: Option<PathBuf>,
+    pub dedup: Vec<DedupeMethod>,
+    pub dedup_mode: DedupeMode,
+    pub dedup_scope: DedupeScope,
+    pub dedup_name_mode: DedupeNameMode,
+    pub dedup_full_hash: bool,
+    /// Only treat name/size matches as duplicates when their contents hash the same too
+    pub dedup_confirm_with_hash: bool,
+    /// Reuse full hashes of unchanged files from this file, and record new ones there
+    pub hash_cache: Option<PathBuf>,
+    /// Prefer duplicates under this path as survivors
+    pub dedup_keep_prefix: Option<PathBuf>,
+    /// Globs of files dedupe never deletes or replaces
+    pub dedup_protect: Vec<String>,
+    /// Write all duplicate groups as CSV here (one row per file)
+    pub dedup_csv: Option<PathBuf>,
+    /// Give each surviving duplicate the oldest mtime of its group
+    pub dedup_inherit_mtime: bool,
+    /// Hardlink mode: symlink duplicates on another filesystem than their survivor instead of keeping them
+    pub dedup_cross_device_symlink: bool,
+    pub dedup_interactive: bool,
+    pub dedup_perceptual_distance: u32,
+    /// Hash files of at least this many bytes through a memory map (0 = never)
+    pub dedup_mmap_threshold: u64,
+    pub dedup_perceptual_delete: bool,
+    /// Only report duplicate groups over `root`; nothing else runs
+    pub dedup_report: bool,
+    /// Print only the first this many duplicate groups; the rest are handled all the same
+    pub dedup_preview_limit: Option<usize>,
+    pub clean: bool,
+    /// With `clean`, delete build-output directories (`junk_dirs.txt`) as a whole
+    pub clean_build_dirs: bool,
+    pub delete_empty_files: bool,
+    pub strict_patterns: bool,
+    pub prune_empty_dirs: bool,
+    /// While pruning, also remove directories that only hold junk (delete-pattern) files
+    pub prune_junk_dirs: bool,
+    pub tree: bool,
+    pub flatten_single: bool,
+    pub include_hidden: bool,
+    /// Also move and clean downloads still in progress (`incomplete_suffixes.txt`)
+    pub include_incomplete: bool,
+    pub follow_symlinks: bool,
+    /// Don't descend into other filesystems mounted below a root
+    pub one_filesystem: bool,
+    pub use_file_cmd: bool,
+    /// Let media, audio and archive signatures win over a file's extension
+    pub trust_content: bool,
+    /// Route files to the category dominating their directory
+    pub context_aware: bool,
+    /// Move files into `YYYY-MM-DD` folders right below the destination root instead of category folders
+    pub by_date_only: bool,
+    /// Split category folders into single-character subfolders by the first letter of each name
+    pub alpha_buckets: bool,
+    /// Command asked for the category of files that would land in Others (path appended)
+    pub categorizer_cmd: Option<String>,
+    pub allow_cross_device: bool,
+    /// Copy into the `under` folder instead of moving; sources are never changed or deleted
+    pub copy: bool,
+    pub fsync: bool,
+    /// Hash-compare cross-device copies before removing the source
+    pub verify_copies: bool,
+    pub skip_open_files: bool,
+    /// What moving a whole directory does when its destination already exists
+    pub dir_conflict: DirConflict,
+    /// What a whole-directory move does when an identical directory is already there
+    pub dedup_dir_mode: DedupeDirMode,
+    pub normalize_names: Option<NameNormalization>,
+    /// Only move files and directories that map to these categories (or rule folders); empty = all
+    pub only_categories: Vec<String>,
+    /// Leave files and directories that map to these categories in place
+    pub skip_categories: Vec<String>,
+    /// Only consider files with these extensions (no dot, any case); empty = all. Whole
+    /// directories then stay where they are
+    pub only_extensions: Vec<String>,
+    /// Leave files with these extensions alone
+    pub ignore_extensions: Vec<String>,
+    /// Leave files above this size in place and out of dedupe
+    pub skip_larger_than: Option<u64>,
+    /// Size `skip_larger_than` goes by: logical length or allocated blocks
+    pub size_metric: SizeMetric,
+    /// Bytes per second for hashing and cross-device copies
+    pub throttle: Option<u64>,
+    /// Filesystem operations per second during execution; 0 means unlimited
+    pub max_ops_per_sec: u32,
+    /// Threads executing independent moves and deletes (0 = one per CPU, 1 = in order)
+    pub jobs: usize,
+    /// Only run deletions, largest first, until this many bytes are freed
+    pub target_free: Option<u64>,
+    pub simulate: Option<PathBuf>,
+    /// Move what would be deleted into a dated folder below this one instead
+    pub quarantine: Option<PathBuf>,
+    /// Skip files recorded (same path, size and mtime) in this journal, and record the
+    /// files seen by this run there
+    pub since_journal: Option<PathBuf>,
+    /// Execute each action as the walk decides it instead of planning everything first
+    pub streaming: bool,
+    /// Fail the walk, before anything is executed, once it finds more files than this
+    pub max_files: Option<usize>,
+    /// Dry run only: save the planned actions and duplicate groups here
+    pub plan_out: Option<PathBuf>,
+    /// Execute a plan saved with `plan_out` instead of planning anew
+    pub plan_in: Option<PathBuf>,
+    pub format: OutputFormat,
+    pub log_file: Option<PathBuf>,
+    pub log_format: LogFormat,
+}
+
+impl Config {
+    pub fn new(root: impl Into<PathBuf>, settings: Settings) -> Self {
+        Self {
+            roots: vec![root.into()],
+            paths: None,
+            settings,
+            apply: false,
+            under: None,
+            dedup: vec![],
+            dedup_mode: DedupeMode::Delete,
+            dedup_scope: DedupeScope::Global,
+            dedup_name_mode: DedupeNameMode::Exact,
+            dedup_full_hash: false,
+            hash_cache: None,
+            dedup_confirm_with_hash: false,
+            dedup_keep_prefix: None,
+            dedup_protect: vec![],
+            dedup_inherit_mtime: false,
+            dedup_cross_device_symlink: false,
+            dedup_csv: None,
+            dedup_interactive: false,
+            dedup_perceptual_distance: DEFAULT_PERCEPTUAL_DISTANCE,
+            dedup_mmap_threshold: DEFAULT_MMAP_THRESHOLD,
+            dedup_perceptual_delete: false,
+            dedup_report: false,
+            dedup_preview_limit: None,
+            clean: true,
+            clean_build_dirs: false,
+            delete_empty_files: false,
+            strict_patterns: false,
+            prune_empty_dirs: true,
+            prune_junk_dirs: false,
+            tree: false,
+            flatten_single: false,
+            include_hidden: false,
+            include_incomplete: false,
+            follow_symlinks: false,
+            one_filesystem: false,
+            use_file_cmd: false,
+            context_aware: false,
+            trust_content: false,
+            by_date_only: false,
+            alpha_buckets: false,
+            categorizer_cmd: None,
+            allow_cross_device: false,
+            copy: false,
+            fsync: false,
+            verify_copies: false,
+            skip_open_files: false,
+            dir_conflict: DirConflict::Suffix,
+            dedup_dir_mode: DedupeDirMode::Off,
+            normalize_names: None,
+            only_categories: vec![],
+            skip_categories: vec![],
+            only_extensions: vec![],
+            ignore_extensions: vec![],
+            skip_larger_than: None,
+            size_metric: SizeMetric::Logical,
+            throttle: None,
+            max_ops_per_sec: 0,
+            jobs: 0,
+            target_free: None,
+            simulate: None,
+            quarantine: None,
+            since_journal: None,
+            streaming: false,
+            max_files: None,
+            plan_out: None,
+            plan_in: None,
+            format: OutputFormat::Text,
+            log_file: None,
+            log_format: LogFormat::Text,
+        }
+    }
+
+    /// Where category folders for files from `root` are created: `root` itself, or the
+    /// shared `--under` folder (resolved against the first root).
+    pub fn dest_root(&self, root: &Path) -> PathBuf {
+        match (&self.under, self.roots.first()) {
+            (Some(under), Some(first)) => first.join(under),
+            _ => root.to_path_buf(),
+        }
+    }
+
+    /// Roots plus any destination root outside all of them, so one scan sees every file
+    /// before and after the moves.
+    fn plan_scan_roots(&self) -> Vec<PathBuf> {
+        let mut scan = self.roots.clone();
+        for dest_root in self.dest_roots() {
+            if !self.roots.iter().any(|r| dest_root.starts_with(r)) {
+                scan.push(dest_root);
+            }
+        }
+        scan
+    }
+
+    /// Each root with its canonical form, for placing listed paths (`paths`) in them.
+    fn canonical_roots(&self) -> Vec<(&PathBuf, PathBuf)> {
+        self.roots.iter().map(|r| (r, std::fs::canonicalize(r).unwrap_or_else(|_| r.clone()))).collect()
+    }
+
+    /// Fill `plan` from the trees in `roots`; with `paths`, from the listed files still where
+    /// they were plus `placed`, wherever moves took the others.
+    /// The hash cache, if any, is saved right away: hashing reads but never changes files.
+    fn scan_duplicates(&self, plan: &mut DedupePlan, roots: &[PathBuf], placed: Vec<PathBuf>) -> Result<()> {
+        match &self.paths {
+            None => plan.scan_all(roots)?,
+            Some(paths) => {
+                let canonical = self.canonical_roots();
+                let listed = paths.iter()
+                    .filter(|p| std::fs::symlink_metadata(p).is_ok_and(|m| m.is_file()))
+                    .filter_map(|p| locate_in_roots(&canonical, p).map(|(_, path)| path));
+                let files: Vec<PathBuf> = placed.into_iter().chain(listed).collect();
+                plan.scan_paths(&self.plan_scan_roots(), &files)?;
+            }
+        }
+        plan.save_hash_cache()
+    }
+
+    /// Whether a directory of category `folder` may move as a whole: it passes the category
+    /// filters and no `--only-ext` picks single files out of the tree.
+    fn whole_dir_selected(&self, folder: &str) -> bool {
+        self.category_selected(folder) && self.only_extensions.is_empty()
+    }
+
+    /// Whether moves into the category (or rule folder) `folder` pass `--only`/`--skip-category`.
+    pub fn category_selected(&self, folder: &str) -> bool {
+        let listed = |list: &[String]| list.iter().any(|c| c.eq_ignore_ascii_case(folder));
+        (self.only_categories.is_empty() || listed(&self.only_categories)) && !listed(&self.skip_categories)
+    }
+
+    /// Distinct destination roots across all roots, in root order.
+    pub fn dest_roots(&self) -> Vec<PathBuf> {
+        let mut out: Vec<PathBuf> = vec![];
+        for root in &self.roots {
+            let dest = self.dest_root(root);
+            if !out.contains(&dest) {
+                out.push(dest);
+            }
+        }
+        out
+    }
+
+    /// Directories the walk never enters: existing category folders, `--under`, `--simulate`
+    /// and `--quarantine`.
+    fn skip_dirs(&self) -> HashSet<PathBuf> {
+        let names = self.settings.category_names();
+        let mut skip: HashSet<PathBuf> = HashSet::new();
+        for dest_root in self.dest_roots() {
+            skip.extend(names.iter().map(|c| dest_root.join(c)));
+            if self.under.is_some() {
+                skip.insert(dest_root);
+            }
+        }
+        skip.extend(self.own_paths());
+        skip
+    }
+
+    /// What organizer itself writes and may keep inside the tree: `--simulate`,
+    /// `--quarantine`, the journal and the hash cache. Neither walked nor deduped.
+    fn own_paths(&self) -> Vec<PathBuf> {
+        [&self.simulate, &self.quarantine, &self.since_journal, &self.hash_cache]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    fn action_engine(&self) -> Result<ActionEngine> {
+        let mut engine = ActionEngine::new(self.apply, self.allow_cross_device, self.log_file.as_ref())?;
+        engine.set_log_format(self.log_format);
+        engine.set_fsync(self.fsync);
+        engine.set_verify_copies(self.verify_copies);
+        engine.set_copy(self.copy);
+        engine.set_dir_conflict(self.dir_conflict);
+        engine.set_dedupe_dirs(self.dedup_dir_mode, self.keep_prefixes());
+        engine.set_dedupe_protect(dedupe::protect_set(&self.dedup_protect)?, self.roots.iter().chain(&self.dest_roots()).cloned().collect());
+        engine.set_normalize_names(self.normalize_names);
+        engine.set_throttle(self.throttle);
+        engine.set_max_ops_per_sec(self.max_ops_per_sec);
+        engine.set_jobs(self.jobs);
+        if let (Some(dir), Some(first)) = (&self.simulate, self.roots.first()) {
+            engine.set_simulate(first, dir.clone());
+        }
+        Ok(engine)
+    }
+
+    /// `dedup_keep_prefix` as absolute paths: as given, or below every root and destination root.
+    fn keep_prefixes(&self) -> Vec<PathBuf> {
+        let Some(prefix) = &self.dedup_keep_prefix else { return vec![] };
+        if prefix.is_absolute() {
+            return vec![prefix.clone()];
+        }
+        self.roots.iter().chain(&self.dest_roots()).map(|r| r.join(prefix)).collect()
+    }
+
+    fn categorizer(&self) -> Result<Categorizer<'_>> {
+        let mut categorizer = Categorizer::new(&self.settings, self.use_file_cmd)?;
+        categorizer.set_external_cmd(self.categorizer_cmd.as_deref());
+        categorizer.set_context_aware(self.context_aware);
+        categorizer.set_trust_content(self.trust_content);
+        Ok(categorizer)
+    }
+
+    fn dedupe_plan(&self) -> Result<DedupePlan> {
+        let mut plan = DedupePlan::new(self.dedup.clone());
+        plan.set_full_hash(self.dedup_full_hash);
+        plan.set_hash_cache(self.hash_cache.as_deref().map(HashCache::load).transpose()?);
+        plan.set_confirm_with_hash(self.dedup_confirm_with_hash);
+        plan.set_perceptual_distance(self.dedup_perceptual_distance);
+        plan.set_scope(self.dedup_scope);
+        plan.set_name_mode(self.dedup_name_mode);
+        plan.set_keep_prefix(self.dedup_keep_prefix.clone());
+        plan.set_protect(&self.dedup_protect)?;
+        plan.set_inherit_mtime(self.dedup_inherit_mtime);
+        plan.set_cross_device_symlink(self.dedup_cross_device_symlink);
+        plan.set_preview_limit(self.dedup_preview_limit);
+        plan.set_max_size(self.skip_larger_than);
+        plan.set_size_metric(self.size_metric);
+        plan.set_throttle(self.throttle);
+        plan.set_mmap_threshold(self.dedup_mmap_threshold);
+        plan.set_protect_marker(&self.settings.protect_marker);
+        if !self.include_incomplete {
+            plan.set_skip_suffixes(&self.settings.incomplete_suffixes);
+        }
+        plan.set_one_filesystem(self.one_filesystem);
+        plan.set_include_hidden(self.include_hidden);
+        plan.skip_paths(self.own_paths());
+        plan.set_extensions(&self.only_extensions, &self.ignore_extensions);
+        Ok(plan)
+    }
+}
+
+/// What a run did (or would do in dry-run).
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunSummary {
+    /// Actions planned by the walk (moves and deletions, before dedupe)
+    pub planned: usize,
+    pub files_moved: usize,
+    pub dirs_moved: usize,
+    pub deleted: usize,
+    /// Duplicates deleted or replaced by links
+    pub duplicates: usize,
+    /// Duplicates that already were hardlinks of the kept file (nothing to free)
+    pub already_linked: usize,
+    pub freed_bytes: u64,
+    /// Actions that failed; the run carried on past them
+    pub errors: usize,
+    /// Each failed action with its error, in the order they ran
+    pub failures: Vec<ActionFailure>,
+    /// What hashing the duplicate scan did, when it hashed anything
+    pub dedupe_stats: Option<DedupeStats>,
+    /// Moves left in place because they would cross filesystems (see `allow_cross_device`)
+    pub cross_device_skipped: usize,
+    /// Files and bytes moved into each top-level destination folder
+    pub categories: BTreeMap<String, CategoryTotals>,
+    /// Wall-clock time of each phase that ran, in order
+    pub phases: Vec<PhaseTiming>,
+    /// Wall-clock time of the whole run, in seconds
+    pub elapsed_seconds: f64,
+    pub applied: bool,
+    /// Stopped early (Ctrl-C) after the action in progress
+    pub interrupted: bool,
+}
+
+/// An action that logged an error. `action` has the same shape as in a `--plan-out` file.
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionFailure {
+    pub action: Action,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct PhaseTiming {
+    pub phase: &'static str,
+    pub seconds: f64,
+}
+
+/// What went into one destination folder; whole-directory moves count every file inside.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct CategoryTotals {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl RunSummary {
+    /// Counters for a run stopped early; only what was executed is counted.
+    fn finish_interrupted(mut self, engine: &ActionEngine) -> Self {
+        self.interrupted = true;
+        self.cross_device_skipped = engine.cross_device_skips();
+        self.freed_bytes = engine.freed_bytes();
+        self.collect_errors(engine);
+        self
+    }
+
+    fn collect_errors(&mut self, engine: &ActionEngine) {
+        self.errors = engine.error_count();
+        self.failures = engine.failures().iter()
+            .map(|(action, error)| ActionFailure { action: action.clone(), error: error.clone() })
+            .collect();
+    }
+
+    fn time(&mut self, phase: &'static str, since: Instant) {
+        self.phases.push(PhaseTiming { phase, seconds: since.elapsed().as_secs_f64() });
+    }
+
+    /// Nothing was planned, deleted or found duplicated.
+    pub fn is_empty(&self) -> bool {
+        self.planned == 0 && self.deleted == 0 && self.duplicates == 0
+    }
+
+    /// Add a move to its destination folder's totals. Call before executing it (the source is measured).
+    fn tally(&mut self, action: &Action, dest_roots: &[PathBuf]) {
+        let (src, dest) = match action {
+            Action::MoveFile(src, dest_dir) => (src, dest_dir),
+            Action::MoveDir(src, dest) => (src, dest),
+            Action::Delete(..) => return,
+        };
+        let folder = dest_roots.iter()
+            .find_map(|r| dest.strip_prefix(r).ok()?.components().next())
+            .map(|c| c.as_os_str())
+            .or(dest.file_name())
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let totals = self.categories.entry(folder).or_default();
+        match action {
+            Action::MoveDir(..) => {
+                for entry in WalkDir::new(src).into_iter().flatten().filter(|e| e.file_type().is_file()) {
+                    totals.files += 1;
+                    totals.bytes += entry.metadata().map(|m| m.len()).unwrap_or(0);
+                }
+            }
+            _ => {
+                totals.files += 1;
+                totals.bytes += std::fs::symlink_metadata(src).map(|m| m.len()).unwrap_or(0);
+            }
+        }
+    }
+
+    fn count<'a>(&mut self, actions: impl IntoIterator<Item = &'a Action>) {
+        for a in actions {
+            match a {
+                Action::MoveFile(..) => self.files_moved += 1,
+                Action::MoveDir(..) => self.dirs_moved += 1,
+                Action::Delete(..) => self.deleted += 1,
+            }
+        }
+    }
+}
+
+/// Extensions of files that would land in Others, with how many files carry each,
+/// most common first. Files in whole-directory moves are not counted.
+pub fn unknown_extensions(config: &Config) -> Result<Vec<(String, usize)>> {
+    let categorizer = config.categorizer()?;
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for action in plan(config)? {
+        let Action::MoveFile(src, _) = action else { continue };
+        let Some(ext) = src.extension().and_then(|e| e.to_str()).map(str::to_lowercase) else { continue };
+        if categorizer.categorize_file(&src)? == Category::Others {
+            *counts.entry(ext).or_default() += 1;
+        }
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+    Ok(counts)
+}
+
+/// Walk result: either a finished action or a file (with its walked root and destination
+/// root) still awaiting categorization.
+enum Planned {
+    Ready(Action),
+    Categorize(PathBuf, PathBuf, PathBuf),
+}
+
+/// Walk every root and plan each move and deletion without touching the filesystem.
+pub fn plan(config: &Config) -> Result<Vec<Action>> {
+    let mut journal = config.since_journal.as_deref().map(Journal::load).transpose()?;
+    plan_with(config, journal.as_mut())
+}
+
+/// `plan`, skipping (and remembering) files through `journal` when given.
+fn plan_with(config: &Config, journal: Option<&mut Journal>) -> Result<Vec<Action>> {
+    let categorizer = config.categorizer()?;
+
+    // Collect actions first; whole-dir detection stays sequential, file categorization is deferred
+    let mut planned: Vec<Planned> = Vec::new();
+    walk(config, &categorizer, journal, None, &mut |p| {
+        planned.push(p);
+        Ok(())
+    })?;
+
+    // Categorize the remaining files in parallel; indexed collect keeps walk order
+    let actions: Vec<Option<Action>> = planned.into_par_iter().map(|p| resolve(&categorizer, config, p)).collect::<Result<_>>()?;
+    Ok(actions.into_iter().flatten().collect())
+}
+
+/// Plan only `entries`, direct children of the roots (e.g. files that just landed), as a full
+/// walk would; everything else below the roots is left out.
+#[cfg_attr(not(feature = "watch"), allow(dead_code))]
+pub(crate) fn plan_entries(config: &Config, categorizer: &Categorizer, entries: &HashSet<PathBuf>) -> Result<Vec<Action>> {
+    let mut planned: Vec<Planned> = Vec::new();
+    walk(config, categorizer, None, Some(entries), &mut |p| {
+        planned.push(p);
+        Ok(())
+    })?;
+    let actions: Vec<Option<Action>> = planned.into_iter().map(|p| resolve(categorizer, config, p)).collect::<Result<_>>()?;
+    Ok(actions.into_iter().flatten().collect())
+}
+
+/// Walk every root (or only the root children in `only`) and hand each decision to `emit` in
+/// walk order. Directories moved as a whole are never entered, so `emit` may execute actions
+/// while the walk goes on.
+fn walk(config: &Config, categorizer: &Categorizer, mut journal: Option<&mut Journal>, only: Option<&HashSet<PathBuf>>, emit: &mut dyn FnMut(Planned) -> Result<()>) -> Result<()> {
+    let settings = &config.settings;
+
+    // Build ignore matcher for delete patterns and avoid scanning our destination categories.
+    // Partial walks repeat, so they stay quiet about settings already reported once.
+    let (delete_matcher, pattern_problems) = settings.delete_matcher()?;
+    if only.is_none() {
+        for problem in &pattern_problems {
+            println!("WARN: {problem}");
+        }
+        for conflict in settings.extension_conflicts() {
+            println!("WARN: {conflict}");
+        }
+    }
+    if config.strict_patterns && !pattern_problems.is_empty() {
+        bail!("{} malformed delete pattern(s); fix them or drop --strict-patterns", pattern_problems.len());
+    }
+    if let Some(paths) = &config.paths {
+        return walk_listed(config, categorizer, journal, paths, &delete_matcher, emit);
+    }
+    let category_dirs: HashSet<String> = settings.category_names().into_iter().collect();
+    let under_name = config.under.as_ref().and_then(|u| u.file_name()).map(|n| n.to_string_lossy().into_owned());
+    let skip_dirs = config.skip_dirs();
+    // Partial walks (watch batches) are small by nature
+    let max_files = config.max_files.filter(|_| only.is_none());
+    let mut found = 0;
+
+    // To avoid recursing into directories we've decided to move as a whole
+    let mut planned_whole_dirs: HashSet<PathBuf> = HashSet::new();
+
+    for root in &config.roots {
+        let dest_root = config.dest_root(root);
+        let root_dev = if config.one_filesystem { std::fs::metadata(root).ok().as_ref().and_then(device_id) } else { None };
+        // Walk the tree (symlinks are only followed on request)
+        let mut it = WalkDir::new(root).follow_links(config.follow_symlinks).into_iter();
+        while let Some(res) = it.next() {
+            // Ctrl-C: plan (or, streaming, execute) nothing more
+            if actions::stop_requested() {
+                break;
+            }
+            let dent = match res {
+                Ok(d) => d,
+                Err(err) => {
+                    println!("WARN: skipping entry due to error: {err}");
+                    continue;
+                }
+            };
+
+            let path = dent.path().to_path_buf();
+
+            // Partial walk: only the listed root children
+            if only.is_some_and(|only| dent.depth() == 1 && !only.contains(&path)) {
+                if dent.file_type().is_dir() {
+                    it.skip_current_dir();
+                }
+                continue;
+            }
+
+            // Skip the root itself in decisions; also skip destination categories and organized root
+            if skip_dirs.iter().any(|p| path.starts_with(p)) {
+                if dent.file_type().is_dir() {
+                    it.skip_current_dir();
+                }
+                continue;
+            }
+
+            // Mounted filesystems below the root are left alone entirely
+            if let Some(dev) = root_dev {
+                if dent.file_type().is_dir() && dent.metadata().ok().as_ref().and_then(device_id).is_some_and(|d| d != dev) {
+                    println!("SKIP {} (other filesystem)", readable_display(&path));
+                    it.skip_current_dir();
+                    continue;
+                }
+            }
+
+            // If any ancestor is a planned whole-dir move, skip its contents
+            if planned_whole_dirs.iter().any(|ancestor| path.starts_with(ancestor)) {
+                if dent.file_type().is_dir() {
+                    it.skip_current_dir();
+                }
+                continue;
+            }
+
+            // Downloads still being written are neither moved nor deleted
+            if !config.include_incomplete && dent.depth() > 0 && settings.is_incomplete(dent.file_name()) {
+                println!("SKIP {} (download in progress)", readable_display(&path));
+                if dent.file_type().is_dir() {
+                    it.skip_current_dir();
+                }
+                continue;
+            }
+
+            // Regenerable build output goes as a whole (hidden ones like `.venv` too)
+            if config.clean && config.clean_build_dirs && dent.file_type().is_dir() && dent.depth() > 0
+                && dent.file_name().to_str().is_some_and(|n| settings.junk_dirs.iter().any(|j| j == n))
+                && !settings.is_protected_dir(&path)
+            {
+                emit(Planned::Ready(Action::Delete(path.clone(), "build artifacts".into())))?;
+                it.skip_current_dir();
+                continue;
+            }
+
+            // Hidden entries are left alone unless asked for (the root itself is always scanned).
+            // Special-dir detection still peeks inside for `.git` and dotfile home markers.
+            if !config.include_hidden && dent.depth() > 0 && is_hidden_name(dent.file_name()) {
+                if dent.file_type().is_dir() {
+                    it.skip_current_dir();
+                }
+                continue;
+            }
+
+            // Handle symlinks (broken)
+            if dent.file_type().is_symlink() {
+                if is_broken_symlink(&path) {
+                    emit(Planned::Ready(Action::Delete(path.clone(), "broken symlink".into())))?;
+                }
+                continue;
+            }
+
+            // If directory: check for special directories to move as whole
+            if dent.file_type().is_dir() {
+                // A marker file asks for the whole subtree to stay exactly as it is
+                if settings.is_protected_dir(&path) {
+                    println!("SKIP {} (protected by {})", readable_display(&path), settings.protect_marker);
+                    it.skip_current_dir();
+                    continue;
+                }
+
+                // Is this a category dir already? Skip
+                let name = dent.file_name().to_string_lossy().to_string();
+                let date_dir = config.by_date_only && path.parent() == Some(dest_root.as_path()) && is_date_name(&name);
+                if category_dirs.contains(&name) || (Some(&name) == under_name.as_ref()) || date_dir {
+                    it.skip_current_dir();
+                    continue;
+                }
+
+                // Detect special: backup/home, project (.git), bare git repo
+                if let Some(dir_cat) = categorizer.detect_special_directory(&path) {
+                    // Filtered out: leave it whole rather than scattering its files
+                    if !config.whole_dir_selected(dir_cat.as_dir()) {
+                        it.skip_current_dir();
+                        continue;
+                    }
+                    let dest_dir = category_dest_dir(categorizer, config, &dest_root, dir_cat, &path).join(path.file_name().unwrap_or_default());
+                    emit(Planned::Ready(Action::MoveDir(path.clone(), dest_dir)))?;
+                    planned_whole_dirs.insert(path.clone());
+                    it.skip_current_dir();
+                    continue;
+                }
+
+                // Else keep walking inside
+                continue;
+            }
+
+            count_file(config, max_files, &mut found)?;
+            let md = dent.metadata().ok();
+            if let Some(planned) = plan_file(config, &delete_matcher, journal.as_deref_mut(), path, md.as_ref(), root, &dest_root) {
+                emit(planned)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `walk` over `Config::paths` instead of the trees. Listed files get the same checks as
+/// walked ones and are skipped where the walk would never reach them; a listed directory only
+/// moves when recognized as a whole (a project, a backup), taking the files below it along.
+/// Paths that are missing or outside every root are skipped with a warning.
+fn walk_listed(config: &Config, categorizer: &Categorizer, mut journal: Option<&mut Journal>, paths: &[PathBuf], delete_matcher: &GlobSet, emit: &mut dyn FnMut(Planned) -> Result<()>) -> Result<()> {
+    let settings = &config.settings;
+    let category_dirs: HashSet<String> = settings.category_names().into_iter().collect();
+    let under_name = config.under.as_ref().and_then(|u| u.file_name());
+    let skip_dirs = config.skip_dirs();
+    let roots = config.canonical_roots();
+    let mut planned_whole_dirs: Vec<PathBuf> = Vec::new();
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    let mut found = 0;
+
+    for listed in paths {
+        if actions::stop_requested() {
+            break;
+        }
+        let Ok(md) = std::fs::symlink_metadata(listed) else {
+            println!("WARN: skipping {} (not found)", readable_display(listed));
+            continue;
+        };
+        let Some((root, path)) = locate_in_roots(&roots, listed) else {
+            println!("WARN: skipping {} (outside the roots)", readable_display(listed));
+            continue;
+        };
+        if !seen.insert(path.clone()) {
+            continue;
+        }
+        let rel = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        let Some(name) = rel.file_name() else { continue }; // the root itself
+        let organized = |c: &std::ffi::OsStr| category_dirs.contains(c.to_string_lossy().as_ref()) || Some(c) == under_name;
+        // Where the walk would never have gone
+        if skip_dirs.iter().chain(&planned_whole_dirs).any(|d| path.starts_with(d))
+            || rel.parent().is_some_and(|p| p.iter().any(organized))
+            || (!config.include_hidden && rel.iter().any(is_hidden_name))
+            || path.ancestors().skip(1).take_while(|a| a.starts_with(root)).any(|a| settings.is_protected_dir(a))
+        {
+            continue;
+        }
+        if !config.include_incomplete && settings.is_incomplete(name) {
+            println!("SKIP {} (download in progress)", readable_display(&path));
+            continue;
+        }
+        let dest_root = config.dest_root(root);
+
+        if md.file_type().is_symlink() {
+            if is_broken_symlink(&path) {
+                emit(Planned::Ready(Action::Delete(path, "broken symlink".into())))?;
+            }
+            continue;
+        }
+        if md.is_dir() {
+            if organized(name) || settings.is_protected_dir(&path) {
+                continue;
+            }
+            if let Some(dir_cat) = categorizer.detect_special_directory(&path) {
+                // Filtered out or not, the files inside stay with it
+                if config.whole_dir_selected(dir_cat.as_dir()) {
+                    let dest_dir = category_dest_dir(categorizer, config, &dest_root, dir_cat, &path).join(name);
+                    emit(Planned::Ready(Action::MoveDir(path.clone(), dest_dir)))?;
+                }
+                planned_whole_dirs.push(path);
+            }
+            continue;
+        }
+        count_file(config, config.max_files, &mut found)?;
+        if let Some(planned) = plan_file(config, delete_matcher, journal.as_deref_mut(), path, Some(&md), root, &dest_root) {
+            emit(planned)?;
+        }
+    }
+    Ok(())
+}
+
+/// The root `path` lies below (the innermost, for nested roots), with `path` spelled from
+/// that root as given, the way the walk would have found it. Only the parent is resolved,
+/// so a listed symlink stays the link.
+fn locate_in_roots<'a>(roots: &[(&'a PathBuf, PathBuf)], path: &Path) -> Option<(&'a PathBuf, PathBuf)> {
+    let abs = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            std::fs::canonicalize(parent).ok()?.join(name)
+        }
+        _ => std::fs::canonicalize(path).ok()?,
+    };
+    let (root, canon) = roots.iter()
+        .filter(|(_, canon)| abs.starts_with(canon))
+        .max_by_key(|(_, canon)| canon.components().count())?;
+    Some((*root, root.join(abs.strip_prefix(canon).ok()?)))
+}
+
+/// `max_files`: one more file was found; more than `max` stops the walk with an error.
+fn count_file(config: &Config, max: Option<usize>, found: &mut usize) -> Result<()> {
+    *found += 1;
+    match max {
+        Some(max) if *found > max => {
+            let roots: Vec<String> = config.roots.iter().map(|r| readable_display(r)).collect();
+            bail!("more than {max} files under {}; nothing was changed. Is this the right root? Point organizer at a narrower one, or raise --max-files", roots.join(", "))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The walk's decision for a regular file: left alone (`None`), deleted, or categorized later.
+fn plan_file(config: &Config, delete_matcher: &GlobSet, journal: Option<&mut Journal>, path: PathBuf, md: Option<&std::fs::Metadata>, root: &Path, dest_root: &Path) -> Option<Planned> {
+    // Filtered-out types are not even seen (nor recorded in the journal)
+    if !extension_allowed(&path, &config.only_extensions, &config.ignore_extensions) {
+        return None;
+    }
+
+    // Incremental runs: files unchanged since an earlier run are left alone
+    if let (Some(journal), Some(md)) = (journal, md) {
+        if journal.check(&path, md) {
+            return None;
+        }
+    }
+
+    // Oversized files stay where they are
+    if let Some(max) = config.skip_larger_than {
+        let size = md.map(|m| config.size_metric.of(m)).unwrap_or(0);
+        if size > max {
+            println!("SKIP {} ({} is larger than {})", readable_display(&path), human_size(size), human_size(max));
+            return None;
+        }
+    }
+
+    // Handle files: delete patterns?
+    if is_pattern_match(delete_matcher, &path) && config.clean {
+        return Some(Planned::Ready(Action::Delete(path, "cache/temp/junk (pattern)".into())));
+    }
+
+    // Empty files? (explicit opt-in, allowlisted marker files are kept)
+    if config.delete_empty_files
+        && md.is_some_and(|m| m.len() == 0)
+        && !config.settings.keep_empty_names.iter().any(|k| path.file_name().is_some_and(|n| n == k.as_str()))
+    {
+        return Some(Planned::Ready(Action::Delete(path, "empty file".into())));
+    }
+
+    // Categorize later (in parallel, unless streaming)
+    Some(Planned::Categorize(path, root.to_path_buf(), dest_root.to_path_buf()))
+}
+
+/// Turn a walk decision into an action, categorizing files that still need it. Files of a
+/// category filtered out by `--only`/`--skip-category`, and files already at their
+/// destination, give `None`.
+fn resolve(categorizer: &Categorizer, config: &Config, planned: Planned) -> Result<Option<Action>> {
+    // Already where it belongs (a re-run): not an action at all
+    Ok(resolve_action(categorizer, config, planned)?.filter(|a| !a.is_in_place(config.normalize_names)))
+}
+
+fn resolve_action(categorizer: &Categorizer, config: &Config, planned: Planned) -> Result<Option<Action>> {
+    let settings = &config.settings;
+    match planned {
+        Planned::Ready(action) => Ok(Some(action)),
+        Planned::Categorize(path, root, dest_root) => {
+            // Rules (and the external command) may route to folders outside the built-in categories
+            let rule = categorizer.rule_target(&path).filter(|f| Category::from_name(f).is_none()).map(str::to_string);
+            let category = match rule {
+                Some(folder) => Err(folder),
+                None => match categorizer.categorize_file(&path)? {
+                    Category::Others => match categorizer.external_target(&path) {
+                        Some(folder) => Category::from_name(&folder).ok_or(folder),
+                        None => Ok(Category::Others),
+                    },
+                    category => Ok(category),
+                },
+            };
+            let (folder, dest_dir) = match category {
+                Ok(category) => (category.as_dir().to_string(), category_dest_dir(categorizer, config, &dest_root, category, &path)),
+                Err(folder) => { let dest_dir = dest_root.join(&folder); (folder, dest_dir) }
+            };
+            let folder = folder.as_str();
+            if !config.category_selected(folder) {
+                return Ok(None);
+            }
+            // A plain day folder instead of the category tree
+            if config.by_date_only {
+                if let Some(date) = categorizer.file_date(&path) {
+                    return Ok(Some(Action::MoveFile(path, dest_root.join(expand_date_template(DATE_ONLY_TEMPLATE, date)))));
+                }
+            }
+            // Optionally keep the folders between the root and the file
+            if settings.preserves_structure(folder) {
+                if let Some(sub) = path.parent().and_then(|p| p.strip_prefix(&root).ok()) {
+                    return Ok(Some(Action::MoveFile(path.clone(), dest_dir.join(sub))));
+                }
+            }
+            Ok(Some(Action::MoveFile(path, dest_dir)))
+        }
+    }
+}
+
+/// Plan and execute a full run: moves and deletions, pruning, flattening, then dedupe.
+/// Progress is logged to stdout (and the log file) as the CLI does.
+pub fn run(config: Config) -> Result<RunSummary> {
+    let started = Instant::now();
+    let mut summary = run_phases(config, started)?;
+    summary.elapsed_seconds = started.elapsed().as_secs_f64();
+    Ok(summary)
+}
+
+fn run_phases(config: Config, started: Instant) -> Result<RunSummary> {
+    // Perceptual is lossy and never implied by `all`
+    let perceptual = config.dedup.contains(&DedupeMethod::Perceptual);
+    if perceptual && !cfg!(feature = "perceptual") {
+        bail!("--dedup perceptual needs a build with `--features perceptual`");
+    }
+
+    // Report-only dedupe: list groups over the whole roots and stop
+    if config.dedup_report {
+        let mut plan = config.dedupe_plan()?;
+        config.scan_duplicates(&mut plan, &config.roots, vec![])?;
+        let dedupe_stats = report_hashing(&plan);
+        let groups = plan.report();
+        print_dedup_report(&groups, config.format, config.dedup_preview_limit)?;
+        write_dedup_csv(&plan, &config)?;
+        return Ok(RunSummary {
+            duplicates: groups.iter().map(|g| g.duplicates.len()).sum(),
+            already_linked: report_linked(&plan),
+            dedupe_stats,
+            ..RunSummary::default()
+        });
+    }
+
+    if config.copy && config.under.is_none() {
+        bail!("--copy needs --under, so the copies don't land among the originals");
+    }
+    if config.streaming && (config.tree || config.target_free.is_some() || config.plan_in.is_some() || config.plan_out.is_some() || config.max_files.is_some()) {
+        bail!("--streaming can't be combined with options that need the whole plan up front");
+    }
+    if config.quarantine.is_some() && config.target_free.is_some() {
+        bail!("--quarantine frees no space, so it can't be combined with --target-free");
+    }
+
+    let dest_roots = config.dest_roots();
+    // Fail once up front rather than on every single move
+    if config.apply && config.simulate.is_none() {
+        for dest_root in &dest_roots {
+            check_writable(dest_root)?;
+        }
+    }
+    let saved = config.plan_in.as_deref().map(SavedPlan::load).transpose()?;
+    let mut journal = config.since_journal.as_deref().map(Journal::load).transpose()?;
+    let scan_started = Instant::now();
+    let planned_actions = match &saved {
+        Some(saved) => saved.validated_actions(),
+        // Streaming decides and executes in one pass below
+        None if config.streaming => Vec::new(),
+        None => plan_with(&config, journal.as_mut())?,
+    };
+    // Mirror mode leaves the source tree alone: no junk or broken-link cleanup there
+    let planned_actions: Vec<Action> = if config.copy {
+        planned_actions.into_iter().filter(|a| !matches!(a, Action::Delete(..))).collect()
+    } else {
+        planned_actions
+    };
+    let mut quarantine = config.quarantine.as_deref().map(|dir| Quarantine::new(dir, &config.roots));
+    let planned_actions: Vec<Action> = match &mut quarantine {
+        Some(q) => planned_actions.into_iter().map(|a| q.rewrite(a)).collect(),
+        None => planned_actions,
+    };
+    let mut summary = RunSummary { planned: planned_actions.len(), ..RunSummary::default() };
+    if !config.streaming {
+        summary.time("scan", scan_started);
+    }
+
+    let mut action_engine = config.action_engine()?;
+    summary.applied = action_engine.apply_mode();
+    // A listed set is deduped where its files end up
+    action_engine.set_record_placements(config.paths.is_some());
+
+    // Space budget: deletions only, largest first, stop at the target
+    if let Some(target) = config.target_free {
+        let mut deletes: Vec<Action> = planned_actions.into_iter()
+            .filter(|a| matches!(a, Action::Delete(..)))
+            .collect();
+        // Perceptual groups stay report-only here too unless explicitly allowed
+        if !config.dedup.is_empty() && (!perceptual || config.dedup_perceptual_delete) {
+            let mut plan = config.dedupe_plan()?;
+            // A file deleted anyway can't be the copy a group keeps
+            plan.skip_paths(deletes.iter().filter_map(|a| match a {
+                Action::Delete(path, _) => Some(path.clone()),
+                _ => None,
+            }));
+            config.scan_duplicates(&mut plan, &config.roots, vec![])?;
+            deletes.extend(plan.duplicate_deletes());
+        }
+        println!("# TARGET-FREE {} from {} candidate deletions", human_size(target), deletes.len());
+        let executed = action_engine.execute_deletes_until(&deletes, target)?;
+        let freed = action_engine.freed_bytes();
+        println!("# DONE. {} deletions selected, {} freed{}{}.",
+            executed,
+            human_size(freed),
+            if freed < target { " (target not reached)" } else { "" },
+            if action_engine.apply_mode() { "" } else { " (dry-run only)" }
+        );
+        summary.deleted = executed;
+        summary.freed_bytes = freed;
+        summary.collect_errors(&action_engine);
+        return Ok(summary);
+    }
+
+    if config.tree {
+        print_plan_tree(&planned_actions, &dest_roots);
+        action_engine.set_quiet(true);
+    }
+
+    // Execute planned moves/deletions
+    action_engine.set_skip_open_files(config.skip_open_files);
+    action_engine.set_protect_marker(&config.settings.protect_marker);
+    let execute_started = Instant::now();
+    if config.streaming {
+        let categorizer = config.categorizer()?;
+        walk(&config, &categorizer, journal.as_mut(), None, &mut |p| {
+            let Some(action) = resolve(&categorizer, &config, p)? else { return Ok(()) };
+            if config.copy && matches!(action, Action::Delete(..)) {
+                return Ok(());
+            }
+            let action = match &mut quarantine {
+                Some(q) => {
+                    let action = q.rewrite(action);
+                    if action_engine.apply_mode() {
+                        q.save_links()?;
+                    }
+                    action
+                }
+                None => action,
+            };
+            if !quarantine.as_ref().is_some_and(|q| q.contains(&action)) {
+                summary.tally(&action, &dest_roots);
+            }
+            action_engine.execute(&action)?;
+            summary.planned += 1;
+            summary.count([&action]);
+            Ok(())
+        })?;
+    } else {
+        for action in planned_actions.iter().filter(|a| !quarantine.as_ref().is_some_and(|q| q.contains(a))) {
+            summary.tally(action, &dest_roots);
+        }
+        // Record broken symlinks before they are deleted
+        if let Some(q) = &mut quarantine {
+            if action_engine.apply_mode() {
+                q.save_links()?;
+            }
+        }
+        let done = action_engine.execute_all(&planned_actions)?;
+        summary.count(done);
+    }
+    // Streaming walks and executes in one go
+    summary.time(if config.streaming { "scan+execute" } else { "execute" }, execute_started);
+    if actions::stop_requested() {
+        let done = summary.files_moved + summary.dirs_moved + summary.deleted;
+        println!("# INTERRUPTED after {} of {} actions; pruning and dedupe were not started.", done, summary.planned);
+        return Ok(summary.finish_interrupted(&action_engine));
+    }
+
+    // Optionally prune empty directories (post-move)
+    if config.prune_empty_dirs && !config.copy {
+        let prune_started = Instant::now();
+        let skip_dirs = config.skip_dirs();
+        let junk = if config.prune_junk_dirs { Some(config.settings.delete_matcher()?.0) } else { None };
+        for root in &config.roots {
+            action_engine.prune_empty_dirs(root, &skip_dirs, junk.as_ref(), quarantine.as_mut())?;
+        }
+        if let Some(q) = &mut quarantine {
+            if action_engine.apply_mode() {
+                q.save_links()?;
+            }
+        }
+        summary.time("prune", prune_started);
+    }
+
+    // Optionally collapse single-child wrapper dirs inside file categories
+    if config.flatten_single {
+        let flatten_started = Instant::now();
+        for cat in Category::ALL.into_iter().filter(|c| !c.is_whole_dir()) {
+            if config.settings.path_templates.contains_key(cat.as_dir()) || config.settings.preserves_structure(cat.as_dir()) { continue; }
+            for dest_root in &dest_roots {
+                action_engine.flatten_single_child_dirs(&dest_root.join(config.settings.dir_name(cat.as_dir())))?;
+            }
+        }
+        summary.time("flatten", flatten_started);
+    }
+
+    // Dedupe phase
+    let dedupe_started = Instant::now();
+    let mut duplicates = Vec::new();
+    if let Some(saved) = &saved {
+        if !saved.duplicates.is_empty() {
+            println!("# DEDUPE from saved plan");
+            // Before --apply nothing has moved yet, so the saved paths can't be checked
+            let groups = if action_engine.apply_mode() { saved.validated_duplicates() } else { saved.duplicates.clone() };
+            summary.duplicates = dedupe::apply_saved(&groups, config.dedup_mode, config.dedup_inherit_mtime, config.dedup_cross_device_symlink, config.dedup_preview_limit, &mut action_engine)?;
+        }
+    } else if !config.dedup.is_empty() {
+        println!("# DEDUPE with methods: {:?}", config.dedup);
+        let mut plan = config.dedupe_plan()?;
+        if config.dedup_interactive && !plan.set_interactive(true) {
+            println!("WARN: stdin is not a terminal; --dedup-interactive keeps the default choice");
+        }
+        if config.plan_out.is_some() {
+            // Nothing has moved in a dry run: find duplicates where the files are now
+            config.scan_duplicates(&mut plan, &config.plan_scan_roots(), vec![])?;
+        } else {
+            config.scan_duplicates(&mut plan, &dest_roots, action_engine.take_placements())?;
+        }
+        summary.dedupe_stats = report_hashing(&plan);
+        // Before acting, so the CSV lists the groups as found
+        write_dedup_csv(&plan, &config)?;
+        if perceptual && !config.dedup_perceptual_delete {
+            println!("# Perceptual groups are report-only (pass --dedup-perceptual-delete to act on them)");
+            print_dedup_report(&plan.report(), config.format, config.dedup_preview_limit)?;
+        } else {
+            if config.plan_out.is_some() {
+                duplicates = plan.report();
+            }
+            summary.duplicates = plan.apply(config.dedup_mode, &mut action_engine)?;
+        }
+        summary.already_linked = report_linked(&plan);
+    }
+    if saved.as_ref().is_some_and(|s| !s.duplicates.is_empty()) || !config.dedup.is_empty() {
+        summary.time("dedupe", dedupe_started);
+    }
+    if actions::stop_requested() {
+        println!("# INTERRUPTED during dedupe; {} duplicates were handled.", summary.duplicates);
+        return Ok(summary.finish_interrupted(&action_engine));
+    }
+
+    if let Some(out) = &config.plan_out {
+        SavedPlan::new(&planned_actions, duplicates).save(out)?;
+        println!("# PLAN saved to {} (run again with --plan-in {} --apply)", out.display(), out.display());
+    }
+
+    if let Some(q) = &quarantine {
+        println!("# QUARANTINE: deletions {} to {}", if action_engine.apply_mode() { "moved" } else { "would move" }, q.dir().display());
+    }
+    if let Some(journal) = &journal {
+        println!("# JOURNAL: {} files unchanged since an earlier run were skipped", journal.unchanged());
+        // A dry run must not mark files as seen
+        if action_engine.apply_mode() {
+            journal.save()?;
+        }
+    }
+    if !summary.categories.is_empty() {
+        print_category_table(&summary.categories, config.format)?;
+    }
+    summary.time("total", started);
+    print_timings(&summary.phases, config.format)?;
+    println!("# DONE. {} actions planned{}.",
+        summary.planned,
+        if action_engine.apply_mode() { " and executed" } else { " (dry-run only)" }
+    );
+    summary.cross_device_skipped = action_engine.cross_device_skips();
+    if summary.cross_device_skipped > 0 {
+        println!("# {} moves skipped across filesystems (pass --allow-cross-device to copy them)", summary.cross_device_skipped);
+    }
+
+    summary.freed_bytes = action_engine.freed_bytes();
+    summary.collect_errors(&action_engine);
+    Ok(summary)
+}
+
+/// Create and remove a probe file in `dir`, or in its nearest existing ancestor when `dir`
+/// doesn't exist yet (`--under`).
+fn check_writable(dir: &Path) -> Result<()> {
+    let Some(existing) = dir.ancestors().find(|a| a.is_dir()) else { return Ok(()) };
+    let probe = existing.join(".organizer-write-test");
+    match std::fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            Ok(())
+        }
+        Err(err) => bail!("destination {} is not writable ({err}); nothing was changed", existing.display()),
+    }
+}
+
+/// Destination tree node; `whole_dir` marks directories moved intact.
+#[derive(Default)]
+struct TreeNode {
+    children: BTreeMap<String, TreeNode>,
+    whole_dir: bool,
+}
+
+fn print_plan_tree(actions: &[Action], dest_roots: &[PathBuf]) {
+    let mut roots: Vec<TreeNode> = dest_roots.iter().map(|_| TreeNode::default()).collect();
+    let mut deletes = 0usize;
+    for a in actions {
+        let (dest, whole_dir) = match a {
+            Action::MoveFile(src, dest_dir) => (dest_dir.join(src.file_name().unwrap_or_default()), false),
+            Action::MoveDir(_, dest) => (dest.clone(), true),
+            Action::Delete(..) => { deletes += 1; continue; }
+        };
+        // Deepest matching root, so a root nested in another keeps its own tree
+        let Some((i, dest_root)) = dest_roots.iter().enumerate()
+            .filter(|(_, r)| dest.starts_with(r))
+            .max_by_key(|(_, r)| r.components().count()) else { continue };
+        let rel = dest.strip_prefix(dest_root).unwrap_or(&dest);
+        let mut node = &mut roots[i];
+        for comp in rel.components() {
+            node = node.children.entry(comp.as_os_str().to_string_lossy().into_owned()).or_default();
+        }
+        node.whole_dir = whole_dir;
+    }
+    for (dest_root, root) in dest_roots.iter().zip(&roots) {
+        println!("{}/", readable_display(dest_root));
+        print_tree_children(root, "");
+    }
+    if deletes > 0 {
+        println!("({} deletions not shown)", deletes);
+    }
+}
+
+fn print_tree_children(node: &TreeNode, prefix: &str) {
+    let count = node.children.len();
+    for (i, (name, child)) in node.children.iter().enumerate() {
+        let last = i + 1 == count;
+        let suffix = if child.whole_dir { "/ (whole dir)" } else if child.children.is_empty() { "" } else { "/" };
+        println!("{}{}{}{}", prefix, if last { "└── " } else { "├── " }, name, suffix);
+        if !child.whole_dir {
+            print_tree_children(child, &format!("{}{}", prefix, if last { "    " } else { "│   " }));
+        }
+    }
+}
+
+/// Count duplicates that are hardlinks of their kept file and say so; nothing is done to them.
+fn report_linked(plan: &DedupePlan) -> usize {
+    let linked = plan.already_linked();
+    let files: usize = linked.iter().map(|g| g.duplicates.len()).sum();
+    if files > 0 {
+        let shared: u64 = linked.iter().map(|g| g.size * g.duplicates.len() as u64).sum();
+        println!("# {} duplicates in {} groups are already hardlinked ({} already shared)", files, linked.len(), human_size(shared));
+    }
+    files
+}
+
+/// One line on how much the duplicate scan had to read, if it hashed at all.
+fn report_hashing(plan: &DedupePlan) -> Option<DedupeStats> {
+    let stats = plan.stats();
+    if stats.files == 0 {
+        return None;
+    }
+    println!("# HASHING {} files in {} sizes, {} sizes shared: {} cached, {} quick and {} full hashes, {} read",
+        stats.files, stats.size_buckets, stats.shared_size_buckets, stats.cached_hashes, stats.quick_hashes, stats.full_hashes, human_size(stats.bytes_read));
+    Some(stats)
+}
+
+fn print_category_table(categories: &BTreeMap<String, CategoryTotals>, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(categories)?);
+        return Ok(());
+    }
+    let width = categories.keys().map(|k| k.chars().count()).max().unwrap_or(0);
+    println!("# CATEGORIES");
+    for (name, t) in categories {
+        println!("{:<width$}  {:>8} files  {:>10}", name, t.files, human_size(t.bytes));
+    }
+    Ok(())
+}
+
+fn write_dedup_csv(plan: &DedupePlan, config: &Config) -> Result<()> {
+    if let Some(path) = &config.dedup_csv {
+        plan.write_report(path)?;
+        println!("# DEDUPE groups written to {}", path.display());
+    }
+    Ok(())
+}
+
+/// Text output: every failed action again, after the per-action lines it may be buried in,
+/// headed by how many of each kind failed. JSON output has them in the summary instead.
+pub fn print_failures(summary: &RunSummary, format: OutputFormat) {
+    if format == OutputFormat::Json || summary.failures.is_empty() {
+        return;
+    }
+    // An action can log several errors (a merged directory); count it once
+    let mut failed: Vec<&Action> = summary.failures.iter().map(|f| &f.action).collect();
+    failed.dedup();
+    let mut counts = [0usize; 3];
+    for a in &failed {
+        counts[match a { Action::MoveFile(..) => 0, Action::MoveDir(..) => 1, Action::Delete(..) => 2 }] += 1;
+    }
+    let kinds = [("move", "moves"), ("directory move", "directory moves"), ("deletion", "deletions")];
+    let counts: Vec<String> = counts.iter().zip(kinds)
+        .filter(|(n, _)| **n > 0)
+        .map(|(n, (one, many))| format!("{} {}", n, if *n == 1 { one } else { many }))
+        .collect();
+    println!("# FAILED {}:", counts.join(", "));
+    for f in &summary.failures {
+        println!("#   {}", f.error);
+    }
+}
+
+/// Final machine-readable summary: the whole `RunSummary` as JSON, on one `# SUMMARY` line
+/// in text mode so log scrapers can pick it out.
+pub fn print_summary(summary: &RunSummary, format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(summary)?),
+        OutputFormat::Text => println!("# SUMMARY {}", serde_json::to_string(summary)?),
+    }
+    Ok(())
+}
+
+fn print_timings(phases: &[PhaseTiming], format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(phases)?);
+        return Ok(());
+    }
+    let parts: Vec<String> = phases.iter().map(|p| format!("{} {:.2}s", p.phase, p.seconds)).collect();
+    println!("# TIMING {}", parts.join(", "));
+    Ok(())
+}
+
+/// Text output lists at most `limit` groups; the totals always cover all of them.
+fn print_dedup_report(groups: &[DupGroup], format: OutputFormat, limit: Option<usize>) -> Result<()> {
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(groups)?);
+        return Ok(());
+    }
+    let shown = limit.unwrap_or(usize::MAX);
+    for (i, g) in groups.iter().enumerate().take(shown) {
+        println!("GROUP {} ({} files)", i + 1, g.duplicates.len() + 1);
+        println!("  KEEP {} ({})", readable_display(&g.keep), human_size(g.size));
+        for d in &g.duplicates {
+            println!("  DUP  {} ({})", readable_display(&d.path), human_size(d.size));
+        }
+    }
+    if groups.len() > shown {
+        println!("# ... and {} more groups not shown", groups.len() - shown);
+    }
+    let dups: usize = groups.iter().map(|g| g.duplicates.len()).sum();
+    let bytes: u64 = groups.iter().flat_map(|g| &g.duplicates).map(|d| d.size).sum();
+    println!("# DONE. {} groups, {} duplicates, {} reclaimable.", groups.len(), dups, human_size(bytes));
+    Ok(())
+}
+
+/// Folder layout of `by_date_only`.
+const DATE_ONLY_TEMPLATE: &str = "{year}-{month}-{day}";
+
+/// `YYYY-MM-DD`, as created by `by_date_only`.
+pub(crate) fn is_date_name(name: &str) -> bool {
+    let b = name.as_bytes();
+    b.len() == 10 && b[4] == b'-' && b[7] == b'-'
+        && b.iter().enumerate().all(|(i, c)| i == 4 || i == 7 || c.is_ascii_digit())
+}
+
+/// Folder inside `dest_root` that `path` goes to as `category`: its dated template folder,
+/// else the category folder (and the name's bucket in it with `alpha_buckets`).
+fn category_dest_dir(categorizer: &Categorizer, config: &Config, dest_root: &Path, category: Category, path: &Path) -> PathBuf {
+    let settings = &config.settings;
+    if let Some(template) = settings.path_templates.get(category.as_dir()) {
+        if let Some(date) = categorizer.file_date(path) {
+            return dest_root.join(expand_date_template(template, date));
+        }
+    }
+    let dir = dest_root.join(settings.dir_name(category.as_dir()));
+    match path.file_name().filter(|_| config.alpha_buckets) {
+        Some(name) => dir.join(alpha_bucket(name)),
+        None => dir,
+    }
+}
@@ -1,255 +1,839 @@
-mod config;
-mod categorize;
-mod dedupe;
-mod actions;
-mod utils;
-
-use crate::categorize::Categorizer;
-use crate::config::Settings;
-use crate::actions::{Action, ActionEngine};
-use crate::dedupe::{DedupeMethod, DedupePlan, DedupeMode};
-use crate::utils::{is_broken_symlink, is_pattern_match, readable_display};
-use anyhow::Result;
-use clap::{ArgAction, Parser, ValueEnum};
-use walkdir::WalkDir;
-use std::collections::HashSet;
-use std::path::PathBuf;
+use anyhow::{bail, Context, Result};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use organizer::config::{config_base, Settings};
+use organizer::dedupe::{DEFAULT_MMAP_THRESHOLD, DEFAULT_PERCEPTUAL_DISTANCE};
+use organizer::utils::{human_size, parse_days, parse_path_list, parse_rate, parse_size, readable_display, NameNormalization, SizeMetric};
+use organizer::{verify, Action, ActionEngine, Categorizer, Category, Config, DedupeDirMode, DedupeMethod, DedupeMode, DedupeNameMode, DedupeScope, DirConflict, LogFormat, OutputFormat};
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use time::macros::format_description;
 use time::OffsetDateTime;
 
 /// CLI args
 #[derive(Parser, Debug)]
 #[command(name="organizer", version, about="Organize, deduplicate, and clean huge folders (dry-run by default).")]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
-    /// Root path to organize. Defaults to current directory.
-    #[arg(value_name="PATH", default_value=".")]
-    root: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Root path(s) to organize. Defaults to current directory. Several roots share one
+    /// plan and one dedupe scan.
+    #[arg(value_name="PATH", default_value=".", num_args=1..)]
+    roots: Vec<PathBuf>,
+
+    /// Organize the paths listed in LIST (one per line, or NUL-separated as from
+    /// `find -print0`) instead of walking the roots; they must lie below a root
+    #[arg(long, value_name="LIST", conflicts_with="watch")]
+    from_file: Option<PathBuf>,
+
+    /// Like --from-file, reading the list from stdin (`fd -e jpg | organizer --stdin ~`)
+    #[arg(long, conflicts_with_all=["from_file", "watch"])]
+    stdin: bool,
 
     /// Apply changes (move/delete). By default, it's a dry run.
     #[arg(long, action=ArgAction::SetTrue)]
     apply: bool,
 
-    /// Create categories under a single folder name (e.g. 'Organized').
-    /// If omitted, categories are created directly in the root.
+    /// Create categories under a single folder name (e.g. 'Organized'), relative to the
+    /// first root unless absolute. If omitted, categories are created directly in each root.
     #[arg(long, value_name="NAME")]
-    under: Option<String>,
+    under: Option<PathBuf>,
 
     /// Enable duplicate handling. May be given multiple times: --dedup name --dedup size --dedup hash
-    /// Or use --dedup all
+    /// Or use --dedup all. `perceptual` (near-duplicate images) is report-only unless
+    /// --dedup-perceptual-delete is given, and needs the `perceptual` build feature.
     #[arg(long, value_enum)]
     dedup: Vec<DedupArg>,
 
-    /// What to do with duplicates: delete (default), hardlink, or symlink
+    /// Maximum Hamming distance between image hashes for `--dedup perceptual`
+    #[arg(long, value_name="BITS", default_value_t=DEFAULT_PERCEPTUAL_DISTANCE)]
+    dedup_perceptual_distance: u32,
+
+    /// Hash files of at least SIZE through a memory map on all cores (0 = always stream).
+    /// Ignored with --throttle
+    #[arg(long, value_name="SIZE", value_parser=parse_size, default_value_t=DEFAULT_MMAP_THRESHOLD)]
+    dedup_mmap_threshold: u64,
+
+    /// Allow acting on (deleting/linking) perceptual duplicate groups
+    #[arg(long, action=ArgAction::SetTrue)]
+    dedup_perceptual_delete: bool,
+
+    /// Only list duplicate groups (kept file, duplicates, sizes) and exit. Never acts, even with --apply.
+    #[arg(long, action=ArgAction::SetTrue, requires="dedup")]
+    dedup_report: bool,
+
+    /// Print only the first N duplicate groups (report) or their actions, then "... and M more".
+    /// All groups are still counted and, with --apply, handled; the log file gets every line
+    #[arg(long, value_name="N", requires="dedup")]
+    dedup_preview_limit: Option<usize>,
+
+    /// Fully hash every file with blake3 instead of the default quick xxh3 pre-pass
+    /// (head/tail 64 KiB + size) that only fully hashes collisions.
+    #[arg(long, action=ArgAction::SetTrue)]
+    dedup_full_hash: bool,
+
+    /// With --dedup name/size: only files whose contents also hash the same are duplicates.
+    /// Name and size just pick the candidates; nothing else is hashed.
+    #[arg(long, action=ArgAction::SetTrue, requires="dedup")]
+    dedup_confirm_with_hash: bool,
+
+    /// Keep full hashes between runs, by path, size and mtime, so unchanged files are not
+    /// read again. Stored as hash-cache.jsonl in the config dir, or in FILE with
+    /// --hash-cache=FILE
+    #[arg(long, value_name="FILE", num_args=0..=1, require_equals=true, requires="dedup")]
+    hash_cache: Option<Option<PathBuf>>,
+
+    /// Keep the duplicate under this path when a group has one (absolute, or relative to the
+    /// scanned root, e.g. Media/Sorted); otherwise the default survivor is kept
+    #[arg(long, value_name="PATH")]
+    dedup_keep_prefix: Option<PathBuf>,
+
+    /// Never delete or replace duplicates matching GLOB (full path or path below the root, e.g.
+    /// 'Originals/**'); a matching file always survives its group. Repeatable
+    #[arg(long, value_name="GLOB")]
+    dedup_protect: Vec<String>,
+
+    /// Set each kept file's mtime to the oldest mtime in its duplicate group, so sorting by
+    /// date still sees when the content first appeared
+    #[arg(long)]
+    dedup_inherit_mtime: bool,
+
+    /// Also write the duplicate groups to FILE as CSV (group, path, size, hash, kept), one
+    /// row per file, for review in a spreadsheet
+    #[arg(long, value_name="FILE", requires="dedup")]
+    dedup_csv: Option<PathBuf>,
+
+    /// Collapse duplicates across all category folders (global), keep one copy per category,
+    /// or only collapse duplicates within one directory (same-dir)
+    #[arg(long, value_enum, default_value_t=DedupScopeArg::Global)]
+    dedup_scope: DedupScopeArg,
+
+    /// Shorthand for --dedup-scope same-dir: copies in different folders (albums) are intentional
+    #[arg(long, conflicts_with="dedup_scope")]
+    dedup_same_dir_only: bool,
+
+    /// Only collapse duplicates below the same first N folders of a root: 2 keeps
+    /// Music/<album> apart from every other album (1 is like --dedup-scope within-category)
+    #[arg(long, value_name="N", value_parser=clap::value_parser!(u64).range(1..), conflicts_with_all=["dedup_scope", "dedup_same_dir_only"], requires="dedup")]
+    dedup_within_depth: Option<u64>,
+
+    /// How --dedup name compares names: exact, case-insensitive, or stem (ignore the extension)
+    #[arg(long, value_enum, default_value_t=DedupNameModeArg::Exact)]
+    dedup_name_mode: DedupNameModeArg,
+
+    /// Ask which file to keep for each duplicate group (or skip it). Falls back to the
+    /// default choice when stdin is not a terminal.
+    #[arg(long, action=ArgAction::SetTrue)]
+    dedup_interactive: bool,
+
+    /// What to do with duplicates: delete (default), hardlink, symlink, or reflink (copy-on-write clone)
     #[arg(long, value_enum, default_value_t=DedupModeArg::Delete)]
     dedup_mode: DedupModeArg,
 
+    /// With --dedup-mode hardlink, replace a duplicate on another filesystem than the kept file
+    /// with a symlink. Otherwise the first such copy is kept and its own filesystem's copies link to it
+    #[arg(long)]
+    dedup_cross_device_symlink: bool,
+
     /// Remove known cache/temp files and broken symlinks
     #[arg(long, default_value_t=true, action=ArgAction::Set)]
     clean: bool,
 
+    /// Also delete build-output directories (node_modules, target, .venv, ... from
+    /// junk_dirs.txt) as a whole. Destructive: review a dry run first
+    #[arg(long, action=ArgAction::SetTrue)]
+    clean_build_dirs: bool,
+
+    /// Delete zero-byte files (names in keep_empty_names.txt, like .gitkeep, are always kept)
+    #[arg(long, default_value_t=false, action=ArgAction::Set)]
+    delete_empty_files: bool,
+
+    /// Treat malformed delete patterns as a hard error instead of a warning
+    #[arg(long, action=ArgAction::SetTrue)]
+    strict_patterns: bool,
+
     /// Remove empty directories after moving
     #[arg(long, default_value_t=true, action=ArgAction::Set)]
     prune_empty_dirs: bool,
 
+    /// While pruning, also remove directories that only contain junk files (delete patterns),
+    /// deleting that junk
+    #[arg(long, action=ArgAction::SetTrue)]
+    prune_junk_dirs: bool,
+
+    /// Show the planned destination layout as a tree instead of one line per move.
+    #[arg(long, action=ArgAction::SetTrue)]
+    tree: bool,
+
+    /// Collapse single-child wrapper directories inside file categories after moving
+    /// (skips whole-directory categories and categories with a path template)
+    #[arg(long, default_value_t=false, action=ArgAction::Set)]
+    flatten_single: bool,
+
+    /// Also organize and clean dotfiles and dot-directories (skipped by default)
+    #[arg(long, action=ArgAction::SetTrue)]
+    include_hidden: bool,
+
+    /// Also move and clean downloads still in progress (names ending in a suffix from
+    /// incomplete_suffixes.txt, such as .crdownload or .part); skipped by default
+    #[arg(long, action=ArgAction::SetTrue)]
+    include_incomplete: bool,
+
     /// Follow symlinks when scanning (dangerous). Default: do not follow.
     #[arg(long, default_value_t=false, action=ArgAction::Set)]
     follow_symlinks: bool,
 
+    /// Stay on the root's filesystem: skip directories that are other mounts (like find -xdev).
+    /// Unix only; a no-op elsewhere
+    #[arg(long, alias="xdev")]
+    one_filesystem: bool,
+
     /// Use `file -b --mime-type` for content detection when extension is unknown (falls back to `infer` crate).
-    #[arg(long, default_value_t=false, action=ArgAction::Set)]
+    #[arg(long, global=true, default_value_t=false, action=ArgAction::Set)]
     use_file_cmd: bool,
 
+    /// File everything into YYYY-MM-DD folders (capture date, else mtime) instead of
+    /// category folders; combine with --only to date just some categories
+    #[arg(long)]
+    by_date_only: bool,
+
+    /// Inside category folders, file everything under its first character: A-Z, 0-9, or #
+    /// (Music/B/Beatles - Help.mp3). Categories with a path template keep their dated folders
+    #[arg(long, conflicts_with="flatten_single")]
+    alpha_buckets: bool,
+
+    /// Detect content for every file and let a media, audio or archive signature win over the
+    /// extension (a JPEG named .txt goes to Media). Slower: every file is opened
+    #[arg(long)]
+    trust_content: bool,
+
+    /// Send a file to the category that dominates its folder (more than half of its files and at
+    /// least three, by extension), e.g. a notes.txt among photos goes to Media with them
+    #[arg(long)]
+    context_aware: bool,
+
+    /// Ask CMD (run with the file path appended) for the category of files that would go to
+    /// Others; it prints a category or rule folder name. Answers are cached per extension
+    #[arg(long, value_name="CMD")]
+    categorizer_cmd: Option<String>,
+
     /// Allow cross-device moves by copy+delete if rename fails with EXDEV.
     #[arg(long, default_value_t=false, action=ArgAction::Set)]
     allow_cross_device: bool,
 
+    /// Before planning, ask which category each unknown extension belongs to and add it
+    /// to that category's extension list for this and future runs
+    #[arg(long, conflicts_with="plan_in")]
+    learn: bool,
+
+    /// Mirror mode: copy files into the --under folder instead of moving them. Sources are
+    /// never modified or deleted; dedupe only touches the copies.
+    #[arg(long, alias="mirror", requires="under", conflicts_with="target_free")]
+    copy: bool,
+
+    /// fsync cross-device copies (and their directories) before deleting the source
+    #[arg(long, action=ArgAction::SetTrue)]
+    fsync: bool,
+
+    /// blake3-compare each cross-device copy with its source before deleting the source
+    #[arg(long = "verify", action=ArgAction::SetTrue)]
+    verify_copies: bool,
+
+    /// Leave files alone that another process still has open (e.g. a browser download
+    /// in progress). Linux only; a no-op on other platforms.
+    #[arg(long, action=ArgAction::SetTrue)]
+    skip_open_files: bool,
+
+    /// When a directory moves onto an existing one: suffix (move it aside as `name-1`),
+    /// merge (move its files in one by one, dropping identical ones) or skip
+    #[arg(long, value_enum, default_value_t=DirConflictArg::Suffix)]
+    dir_conflict: DirConflictArg,
+
+    /// When a directory moves onto an identical one (same files, same contents): off (leave
+    /// it to --dir-conflict), keep-existing (delete the incoming copy) or keep-preferred (keep
+    /// the copy under --dedup-keep-prefix, else the path that sorts first). Either way a copy
+    /// holding a --dedup-protect match is kept
+    #[arg(long, value_enum, default_value_t=DedupDirModeArg::Off)]
+    dedup_dir_mode: DedupDirModeArg,
+
+    /// Clean up names of moved files: collapse whitespace and lowercase the extension
+    #[arg(long, action=ArgAction::SetTrue)]
+    normalize_names: bool,
+
+    /// With --normalize-names, replace spaces with underscores
+    #[arg(long, action=ArgAction::SetTrue, requires="normalize_names")]
+    normalize_underscores: bool,
+
+    /// With --normalize-names, transliterate non-ASCII characters (`café` -> `cafe`)
+    #[arg(long, action=ArgAction::SetTrue, requires="normalize_names")]
+    normalize_ascii: bool,
+
+    /// Only move files (and whole directories) of this category; repeatable, e.g. --only Media
+    #[arg(long = "only", value_name="CATEGORY")]
+    only_categories: Vec<String>,
+
+    /// Leave files (and whole directories) of this category where they are; repeatable
+    #[arg(long = "skip-category", value_name="CATEGORY")]
+    skip_categories: Vec<String>,
+
+    /// Only look at files with these extensions, e.g. --only-ext jpg,png,mp4 (repeatable).
+    /// Everything else, whole project and backup folders included, stays put and out of dedupe
+    #[arg(long = "only-ext", value_name="EXT", value_delimiter=',')]
+    only_extensions: Vec<String>,
+
+    /// Leave files with these extensions alone (not moved, cleaned or deduped), e.g. --ignore-ext iso
+    #[arg(long = "ignore-ext", value_name="EXT", value_delimiter=',')]
+    ignore_extensions: Vec<String>,
+
+    /// Leave files larger than SIZE (e.g. 50G) untouched: not moved, deleted or hashed for dedupe
+    #[arg(long, value_name="SIZE", value_parser=parse_size)]
+    skip_larger_than: Option<u64>,
+
+    /// Size that --skip-larger-than goes by: logical (the length) or allocated (disk blocks
+    /// in use, so sparse files count for what they occupy; Unix only)
+    #[arg(long, value_enum, default_value_t=SizeMetricArg::Logical)]
+    size_metric: SizeMetricArg,
+
+    /// Limit hashing reads and cross-device copies to this many MiB/s (e.g. 20)
+    #[arg(long, value_name="MIB_PER_SEC", value_parser=parse_rate)]
+    throttle: Option<u64>,
+
+    /// Perform at most N moves/deletes per second (0 = unlimited), to spare network mounts
+    #[arg(long, value_name="N", default_value_t=0)]
+    max_ops_per_sec: u32,
+
+    /// Threads for executing independent moves and deletes (0 = one per CPU). Moves into
+    /// the same folder stay in order; use 1 to run everything strictly in plan order
+    #[arg(long, short='j', value_name="N", default_value_t=0)]
+    jobs: usize,
+
+    /// Only free space: run deletions (junk, then duplicates) largest-first until SIZE is reclaimed
+    /// (e.g. 5G), skipping all moves.
+    #[arg(long, value_name="SIZE", value_parser=parse_size)]
+    target_free: Option<u64>,
+
+    /// Simulate the run by creating zero-byte placeholders for every move under DIR.
+    /// The real tree is never touched; deletes are only logged.
+    #[arg(long, value_name="DIR")]
+    simulate: Option<PathBuf>,
+
+    /// Instead of deleting junk, empty files and build dirs, move them into DIR/<YYYY-MM-DD>/
+    /// (keeping their path below the root) for review. Broken symlinks are listed in
+    /// broken-symlinks.txt there and deleted. Duplicates are not affected
+    #[arg(long, alias="backup-before-delete", value_name="DIR", conflicts_with="target_free")]
+    quarantine: Option<PathBuf>,
+
+    /// Incremental runs: skip files listed unchanged (path, size, mtime) in FILE, then record
+    /// the files this run left in place there. The journal is only updated with --apply
+    #[arg(long, value_name="FILE")]
+    since_journal: Option<PathBuf>,
+
+    /// Execute each move/delete as soon as the walk decides it, keeping memory flat on huge
+    /// trees. There is no upfront plan: files are categorized one by one (not in parallel)
+    #[arg(long, conflicts_with_all=["tree", "target_free", "plan_out", "plan_in"])]
+    streaming: bool,
+
+    /// Safety cap: stop before changing anything when the walk finds more than N files,
+    /// e.g. when pointed at a whole home directory by mistake. Unlimited by default
+    #[arg(long, value_name="N", conflicts_with="streaming")]
+    max_files: Option<usize>,
+
+    /// After the run, keep watching the roots and organize new files as they land (once
+    /// they have been quiet for 2s; .part/.crdownload downloads wait). Needs the `watch` feature
+    #[arg(long, conflicts_with_all=["tree", "target_free", "plan_out", "plan_in", "simulate", "dedup_report", "streaming"])]
+    watch: bool,
+
+    /// Save the dry-run plan (moves, deletes, duplicate groups) as JSON to FILE
+    #[arg(long, value_name="FILE", conflicts_with="apply")]
+    plan_out: Option<PathBuf>,
+
+    /// Run a plan saved with --plan-out instead of planning again; actions whose
+    /// source is gone or changed size are skipped
+    #[arg(long, value_name="FILE", conflicts_with="plan_out")]
+    plan_in: Option<PathBuf>,
+
+    /// Output format for reports
+    #[arg(long, global=true, value_enum, default_value_t=FormatArg::Text)]
+    format: FormatArg,
+
     /// Log file to append detailed actions (in addition to stdout).
     #[arg(long, value_name="FILE")]
     log_file: Option<PathBuf>,
 
+    /// Read (and write default) config lists from this directory instead of ~/.config/organizer
+    #[arg(long, global=true, value_name="PATH")]
+    config_dir: Option<PathBuf>,
+
+    /// Use the named rule set in <config dir>/profiles/NAME/; lists it lacks come from the base
+    #[arg(long, global=true, value_name="NAME")]
+    profile: Option<String>,
+
+    /// Format of the log file: text (same lines as stdout) or jsonl (one JSON object per action)
+    #[arg(long, value_enum, default_value_t=LogFormatArg::Text)]
+    log_format: LogFormatArg,
+
+    /// Print each category with its destination folder and extensions, then exit
+    #[arg(long, action=ArgAction::SetTrue)]
+    list_categories: bool,
+
+    /// Print the effective settings as TOML (for organizer.toml) and exit
+    #[arg(long, action=ArgAction::SetTrue)]
+    dump_config: bool,
+
     /// Skip creating default config files if missing
-    #[arg(long, default_value_t=false, action=ArgAction::Set)]
+    #[arg(long, global=true, default_value_t=false, action=ArgAction::Set)]
     no_write_defaults: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Re-categorize files already inside category folders and report misfiled items (never moves).
+    Verify {
+        /// Organized tree containing the category folders
+        #[arg(value_name="TREE")]
+        tree: PathBuf,
+
+        /// Also print the MOVE plan that would fix the misfiled items
+        #[arg(long, action=ArgAction::SetTrue)]
+        plan: bool,
+    },
+    /// Permanently delete the dated folders of a --quarantine DIR older than a cutoff.
+    /// Dry run unless --apply is given; never runs as part of an organize run.
+    EmptyQuarantine {
+        /// Quarantine directory (the one passed to --quarantine)
+        #[arg(value_name="DIR")]
+        dir: PathBuf,
+
+        /// Remove folders quarantined more than this long ago, e.g. 30d or 4w
+        #[arg(long, value_name="AGE", value_parser=parse_days)]
+        trash_older_than: u64,
+
+        /// Actually delete (default: dry run)
+        #[arg(long, action=ArgAction::SetTrue)]
+        apply: bool,
+    },
+}
+
 #[derive(Clone, Debug, ValueEnum)]
 enum DedupArg {
     All,
     Name,
     Size,
     Hash,
+    Perceptual,
 }
 
-#[derive(Clone, Debug, clap::ValueEnum)]
-enum DedupModeArg {
-    Delete,
-    Hardlink,
-    Symlink,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum FormatArg {
+    Text,
+    Json,
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-
-    // Timestamp header
-    let now = OffsetDateTime::now_utc();
-    let fmt = format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
-    println!("# organizer @ {}", now.format(fmt).unwrap_or_default());
-    println!("# Root: {}", readable_display(&cli.root));
-    println!("# Mode: {}", if cli.apply { "APPLY (will change files!)" } else { "DRY-RUN (no changes)" });
-
-    // Load settings + ensure default config files exist
-    let settings = Settings::load_or_default()?;
-    if !cli.no_write_defaults {
-        settings.ensure_default_lists_written()?;
+impl From<FormatArg> for OutputFormat {
+    fn from(arg: FormatArg) -> Self {
+        match arg {
+            FormatArg::Text => OutputFormat::Text,
+            FormatArg::Json => OutputFormat::Json,
+        }
     }
+}
 
-    // Categorizer
-    let categorizer = Categorizer::new(&settings, cli.use_file_cmd)?;
-
-    // Calculate destination root (maybe under an "Organized" folder or directly in root)
-    let dest_root = if let Some(name) = &cli.under {
-        cli.root.join(name)
-    } else {
-        cli.root.clone()
-    };
-
-    // Ensure category directories exist in DRY-RUN? We will create only in APPLY phase.
-    let mut action_engine = ActionEngine::new(cli.apply, cli.allow_cross_device, cli.log_file.as_ref())?;
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum DedupScopeArg {
+    Global,
+    WithinCategory,
+    SameDir,
+}
 
-    // Build ignore matcher for delete patterns and avoid scanning our destination categories
-    let delete_matcher = settings.delete_matcher()?;
-    let category_dirs: HashSet<String> = settings.category_names().into_iter().collect();
-    let mut skip_dirs: HashSet<PathBuf> = HashSet::new();
-    // Skip destination categories already present
-    for cat in &category_dirs {
-        skip_dirs.insert(dest_root.join(cat));
-    }
-    if let Some(name) = &cli.under {
-        skip_dirs.insert(cli.root.join(name));
+impl From<DedupScopeArg> for DedupeScope {
+    fn from(arg: DedupScopeArg) -> Self {
+        match arg {
+            DedupScopeArg::Global => DedupeScope::Global,
+            DedupScopeArg::WithinCategory => DedupeScope::WithinCategory,
+            DedupScopeArg::SameDir => DedupeScope::SameDirectory,
+        }
     }
+}
 
-    // Walk the tree using ignore::WalkBuilder (respects .gitignore, can follow symlinks optional)
-    let mut it = WalkDir::new(&cli.root).follow_links(cli.follow_symlinks).into_iter();
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum SizeMetricArg {
+    Logical,
+    Allocated,
+}
 
-    // To avoid recursing into directories we've decided to move as a whole
-    let mut planned_whole_dirs: HashSet<PathBuf> = HashSet::new();
+impl From<SizeMetricArg> for SizeMetric {
+    fn from(arg: SizeMetricArg) -> Self {
+        match arg {
+            SizeMetricArg::Logical => SizeMetric::Logical,
+            SizeMetricArg::Allocated => SizeMetric::Allocated,
+        }
+    }
+}
 
-    // Collect actions first
-    let mut planned_actions: Vec<Action> = Vec::new();
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum DedupNameModeArg {
+    Exact,
+    CaseInsensitive,
+    Stem,
+}
 
-    while let Some(res) = it.next() {
-        let dent = match res {
-            Ok(d) => d,
-            Err(err) => {
-                println!("WARN: skipping entry due to error: {err}");
-                continue;
-            }
-        };
+impl From<DedupNameModeArg> for DedupeNameMode {
+    fn from(arg: DedupNameModeArg) -> Self {
+        match arg {
+            DedupNameModeArg::Exact => DedupeNameMode::Exact,
+            DedupNameModeArg::CaseInsensitive => DedupeNameMode::CaseInsensitive,
+            DedupNameModeArg::Stem => DedupeNameMode::Stem,
+        }
+    }
+}
 
-        let path = dent.path().to_path_buf();
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum DirConflictArg {
+    Suffix,
+    Merge,
+    Skip,
+}
 
-        // Skip the root itself in decisions; also skip destination categories and organized root
-        if skip_dirs.iter().any(|p| path.starts_with(p)) {
-            if dent.file_type().is_dir() {
-                it.skip_current_dir();
-            }
-            continue;
+impl From<DirConflictArg> for DirConflict {
+    fn from(arg: DirConflictArg) -> Self {
+        match arg {
+            DirConflictArg::Suffix => DirConflict::Suffix,
+            DirConflictArg::Merge => DirConflict::Merge,
+            DirConflictArg::Skip => DirConflict::Skip,
         }
+    }
+}
 
-        // If any ancestor is a planned whole-dir move, skip its contents
-        if planned_whole_dirs.iter().any(|ancestor| path.starts_with(ancestor)) {
-            if dent.file_type().is_dir() {
-                it.skip_current_dir();
-            }
-            continue;
-        }
+#[derive(Clone, Debug, ValueEnum)]
+enum DedupDirModeArg {
+    Off,
+    KeepExisting,
+    KeepPreferred,
+}
 
-        // Handle symlinks (broken)
-        if dent.file_type().is_symlink() {
-            if is_broken_symlink(&path) {
-                planned_actions.push(Action::Delete(path.clone(), "broken symlink".into()));
-            }
-            continue;
+impl From<DedupDirModeArg> for DedupeDirMode {
+    fn from(arg: DedupDirModeArg) -> Self {
+        match arg {
+            DedupDirModeArg::Off => DedupeDirMode::Off,
+            DedupDirModeArg::KeepExisting => DedupeDirMode::KeepExisting,
+            DedupDirModeArg::KeepPreferred => DedupeDirMode::KeepPreferred,
         }
+    }
+}
 
-        // If directory: check for special directories to move as whole
-        if dent.file_type().is_dir() {
-            // Is this a category dir already? Skip
-            let name = dent.file_name().to_string_lossy().to_string();
-            if category_dirs.contains(&name) || (Some(&name) == cli.under.as_ref()) {
-                it.skip_current_dir();
-                continue;
-            }
+#[derive(Clone, Debug, ValueEnum)]
+enum LogFormatArg {
+    Text,
+    Jsonl,
+}
 
-            // Detect special: backup/home, project (.git), bare git repo
-            if let Some(dir_cat) = categorizer.detect_special_directory(&path) {
-                let dest_dir = dest_root.join(dir_cat.as_dir()).join(path.file_name().unwrap_or_default());
-                planned_actions.push(Action::MoveDir(path.clone(), dest_dir));
-                planned_whole_dirs.insert(path.clone());
-                it.skip_current_dir();
-                continue;
-            }
+#[derive(Clone, Debug, clap::ValueEnum)]
+enum DedupModeArg {
+    Delete,
+    Hardlink,
+    Symlink,
+    Reflink,
+}
 
-            // Else keep walking inside
-            continue;
+/// Some actions failed (also used for fatal errors, which exit through `Err`).
+const EXIT_PARTIAL_FAILURE: u8 = 1;
+/// A dry run found nothing to do. (2 is clap's usage error.)
+const EXIT_NOTHING_TO_DO: u8 = 3;
+/// Stopped by Ctrl-C (128 + SIGINT, as shells report it).
+const EXIT_INTERRUPTED: u8 = 130;
+
+fn main() -> Result<ExitCode> {
+    let mut cli = Cli::parse();
+    // A symlinked root would be walked as a single link entry
+    for root in &mut cli.roots {
+        if std::fs::symlink_metadata(&*root).is_ok_and(|md| md.file_type().is_symlink()) {
+            *root = std::fs::canonicalize(&*root).with_context(|| format!("resolve root {}", root.display()))?;
         }
+    }
 
-        // Handle files: delete patterns?
-        if is_pattern_match(&delete_matcher, &path) && cli.clean {
-            planned_actions.push(Action::Delete(path.clone(), "cache/temp/junk (pattern)".into()));
-            continue;
-        }
+    if let Some(Command::Verify { tree, plan }) = &cli.command {
+        run_verify(&cli, tree, *plan)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::EmptyQuarantine { dir, trash_older_than, apply }) = &cli.command {
+        return run_empty_quarantine(dir, *trash_older_than, *apply);
+    }
 
-        // Empty files?
-        if dent.metadata().map(|m| m.len() == 0).unwrap_or(false) && cli.clean {
-            planned_actions.push(Action::Delete(path.clone(), "empty file".into()));
-            continue;
-        }
+    // Before the run, not after a whole pass
+    if cli.watch && !cfg!(feature = "watch") {
+        bail!("--watch needs a build with `--features watch`");
+    }
 
-        // Categorize file and plan move
-        let category = categorizer.categorize_file(&path)?;
-        let dest_dir = dest_root.join(category.as_dir());
-        planned_actions.push(Action::MoveFile(path.clone(), dest_dir));
+    if cli.list_categories {
+        print_categories(&load_settings(&cli)?);
+        return Ok(ExitCode::SUCCESS);
     }
 
-    // Execute planned moves/deletions
-    action_engine.execute_all(&planned_actions)?;
+    if cli.dump_config {
+        print!("{}", load_settings(&cli)?.to_toml()?);
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    // Optionally prune empty directories (post-move)
-    if cli.prune_empty_dirs {
-        action_engine.prune_empty_dirs(&cli.root, &skip_dirs)?;
+    // Timestamp header (kept out of JSON output)
+    if cli.format == FormatArg::Text {
+        let now = OffsetDateTime::now_utc();
+        let fmt = format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
+        println!("# organizer @ {}", now.format(fmt).unwrap_or_default());
+        let roots: Vec<String> = cli.roots.iter().map(|r| readable_display(r)).collect();
+        println!("# Root: {}", roots.join(", "));
+        if let Some(dir) = &cli.simulate {
+            println!("# Mode: SIMULATE into {} (no changes to root)", readable_display(dir));
+        } else {
+            println!("# Mode: {}", if cli.apply { "APPLY (will change files!)" } else { "DRY-RUN (no changes)" });
+        }
     }
 
-    // Dedupe phase
-    let dedup_methods: Vec<DedupeMethod> = if cli.dedup.is_empty() {
-        vec![]
-    } else if cli.dedup.iter().any(|d| matches!(d, DedupArg::All)) {
+    let settings = load_settings(&cli)?;
+    let mut dedup: Vec<DedupeMethod> = if cli.dedup.iter().any(|d| matches!(d, DedupArg::All)) {
         vec![DedupeMethod::Name, DedupeMethod::Size, DedupeMethod::Hash]
     } else {
-        cli.dedup.iter().map(|d| match d {
-            DedupArg::Name => DedupeMethod::Name,
-            DedupArg::Size => DedupeMethod::Size,
-            DedupArg::Hash => DedupeMethod::Hash,
-            DedupArg::All => unreachable!(),
+        cli.dedup.iter().filter_map(|d| match d {
+            DedupArg::Name => Some(DedupeMethod::Name),
+            DedupArg::Size => Some(DedupeMethod::Size),
+            DedupArg::Hash => Some(DedupeMethod::Hash),
+            DedupArg::All | DedupArg::Perceptual => None,
         }).collect()
     };
+    if cli.dedup.iter().any(|d| matches!(d, DedupArg::Perceptual)) {
+        dedup.push(DedupeMethod::Perceptual);
+    }
+
+    let paths = match (&cli.from_file, cli.stdin) {
+        (Some(list), _) => Some(parse_path_list(&std::fs::read(list).with_context(|| format!("read {}", list.display()))?)),
+        (None, true) => {
+            let mut bytes = Vec::new();
+            std::io::Read::read_to_end(&mut std::io::stdin(), &mut bytes).context("read paths from stdin")?;
+            Some(parse_path_list(&bytes))
+        }
+        (None, false) => None,
+    };
 
-    if !dedup_methods.is_empty() {
-        println!("# DEDUPE with methods: {:?}", dedup_methods);
-        let mode = match cli.dedup_mode {
+    let mut config = Config {
+        paths,
+        dedup,
+        apply: cli.apply,
+        under: cli.under,
+        dedup_mode: match cli.dedup_mode {
             DedupModeArg::Delete => DedupeMode::Delete,
             DedupModeArg::Hardlink => DedupeMode::Hardlink,
             DedupModeArg::Symlink => DedupeMode::Symlink,
+            DedupModeArg::Reflink => DedupeMode::Reflink,
+        },
+        dedup_scope: match cli.dedup_within_depth {
+            Some(depth) => DedupeScope::WithinDepth(depth as usize),
+            None if cli.dedup_same_dir_only => DedupeScope::SameDirectory,
+            None => cli.dedup_scope.into(),
+        },
+        dedup_name_mode: cli.dedup_name_mode.into(),
+        dedup_full_hash: cli.dedup_full_hash,
+        hash_cache: cli.hash_cache.map(|file| file.unwrap_or_else(|| config_base(cli.config_dir.as_deref()).join("hash-cache.jsonl"))),
+        dedup_confirm_with_hash: cli.dedup_confirm_with_hash,
+        dedup_keep_prefix: cli.dedup_keep_prefix,
+        dedup_protect: cli.dedup_protect,
+        dedup_inherit_mtime: cli.dedup_inherit_mtime,
+        dedup_cross_device_symlink: cli.dedup_cross_device_symlink,
+        dedup_csv: cli.dedup_csv,
+        dedup_interactive: cli.dedup_interactive,
+        dedup_perceptual_distance: cli.dedup_perceptual_distance,
+        dedup_mmap_threshold: cli.dedup_mmap_threshold,
+        dedup_perceptual_delete: cli.dedup_perceptual_delete,
+        dedup_report: cli.dedup_report,
+        dedup_preview_limit: cli.dedup_preview_limit,
+        clean: cli.clean,
+        clean_build_dirs: cli.clean_build_dirs,
+        delete_empty_files: cli.delete_empty_files,
+        strict_patterns: cli.strict_patterns,
+        prune_empty_dirs: cli.prune_empty_dirs,
+        prune_junk_dirs: cli.prune_junk_dirs,
+        tree: cli.tree,
+        flatten_single: cli.flatten_single,
+        include_hidden: cli.include_hidden,
+        include_incomplete: cli.include_incomplete,
+        follow_symlinks: cli.follow_symlinks,
+        one_filesystem: cli.one_filesystem,
+        use_file_cmd: cli.use_file_cmd,
+        context_aware: cli.context_aware,
+        trust_content: cli.trust_content,
+        by_date_only: cli.by_date_only,
+        alpha_buckets: cli.alpha_buckets,
+        categorizer_cmd: cli.categorizer_cmd,
+        allow_cross_device: cli.allow_cross_device,
+        copy: cli.copy,
+        fsync: cli.fsync,
+        verify_copies: cli.verify_copies,
+        skip_open_files: cli.skip_open_files,
+        dir_conflict: cli.dir_conflict.into(),
+        dedup_dir_mode: cli.dedup_dir_mode.into(),
+        normalize_names: cli.normalize_names.then_some(NameNormalization {
+            underscores: cli.normalize_underscores,
+            ascii: cli.normalize_ascii,
+        }),
+        only_categories: cli.only_categories,
+        skip_categories: cli.skip_categories,
+        only_extensions: cli.only_extensions,
+        ignore_extensions: cli.ignore_extensions,
+        skip_larger_than: cli.skip_larger_than,
+        size_metric: cli.size_metric.into(),
+        throttle: cli.throttle,
+        max_ops_per_sec: cli.max_ops_per_sec,
+        jobs: cli.jobs,
+        target_free: cli.target_free,
+        simulate: cli.simulate,
+        quarantine: cli.quarantine,
+        since_journal: cli.since_journal,
+        streaming: cli.streaming,
+        max_files: cli.max_files,
+        plan_out: cli.plan_out,
+        plan_in: cli.plan_in,
+        format: cli.format.into(),
+        log_file: cli.log_file,
+        log_format: match cli.log_format {
+            LogFormatArg::Text => LogFormat::Text,
+            LogFormatArg::Jsonl => LogFormat::Jsonl,
+        },
+        roots: cli.roots,
+        ..Config::new(PathBuf::new(), settings)
+    };
+    if cli.learn {
+        learn_extensions(&mut config)?;
+    }
+    // First Ctrl-C finishes the action in progress and stops; a second one quits at once
+    ctrlc::set_handler(|| {
+        if organizer::actions::stop_requested() {
+            std::process::exit(EXIT_INTERRUPTED.into());
+        }
+        eprintln!("Stopping after the current action (Ctrl-C again to quit now)...");
+        organizer::actions::request_stop();
+    })?;
+    let watch = cli.watch.then(|| config.clone());
+    let summary = organizer::run(config)?;
+    // Ctrl-C is how watching ends, so it counts as a clean stop there
+    let summary = match watch {
+        Some(config) if !summary.interrupted => organizer::watch::watch(&config)?,
+        _ => summary,
+    };
+    organizer::print_failures(&summary, cli.format.into());
+    organizer::print_summary(&summary, cli.format.into())?;
+    Ok(if summary.interrupted {
+        ExitCode::from(EXIT_INTERRUPTED)
+    } else if summary.errors > 0 {
+        ExitCode::from(EXIT_PARTIAL_FAILURE)
+    } else if !summary.applied && summary.is_empty() {
+        ExitCode::from(EXIT_NOTHING_TO_DO)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Ask once per unknown extension which category it belongs to and save the answers.
+fn learn_extensions(config: &mut Config) -> Result<()> {
+    if !std::io::stdin().is_terminal() {
+        println!("WARN: stdin is not a terminal; --learn asks nothing");
+        return Ok(());
+    }
+    let unknown = organizer::unknown_extensions(config)?;
+    println!("# LEARN: {} unknown extensions", unknown.len());
+    let stdin = std::io::stdin();
+    for (ext, count) in unknown {
+        let category = loop {
+            print!(".{} ({} files): [m]edia, m[u]sic, [d]ocuments, [a]rchives, [s]oftware, [c]ode, or Enter to skip: ", ext, count);
+            std::io::stdout().flush()?;
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            match line.trim().to_lowercase().as_str() {
+                "" => break None,
+                "m" | "media" => break Some("Media"),
+                "u" | "music" => break Some("Music"),
+                "d" | "documents" => break Some("Documents"),
+                "a" | "archives" => break Some("Archives"),
+                "s" | "software" => break Some("Software"),
+                "c" | "code" => break Some("Code"),
+                _ => println!("Unknown choice"),
+            }
         };
-        let mut plan = DedupePlan::new(dedup_methods);
-        plan.scan(&dest_root)?;
-        plan.apply(mode, &mut action_engine)?;
+        if let Some(category) = category {
+            config.settings.learn_extension(category, &ext)?;
+        }
     }
+    Ok(())
+}
 
-    println!("# DONE. {} actions planned{}.",
-        planned_actions.len(),
-        if action_engine.apply_mode() { " and executed" } else { " (dry-run only)" }
-    );
+fn load_settings(cli: &Cli) -> Result<Settings> {
+    let base = config_base(cli.config_dir.as_deref());
+    let settings = match &cli.profile {
+        Some(name) => Settings::load_profile(&base, name)?,
+        None => Settings::load_or_default(&base)?,
+    };
+    if !cli.no_write_defaults {
+        settings.ensure_default_lists_written()?;
+    }
+    Ok(settings)
+}
+
+/// Effective routing: destination and matching rule for every category, plus custom rule folders.
+fn print_categories(settings: &Settings) {
+    for cat in Category::ALL {
+        let dest = settings.path_templates.get(cat.as_dir()).map(String::as_str).unwrap_or(settings.dir_name(cat.as_dir()));
+        let matches = match cat {
+            Category::Projects => format!("directories with .git, or >= {} code files ({})",
+                settings.code_file_threshold, settings.code_exts.join(" ")),
+            Category::GitRepos => "bare git repositories".to_string(),
+            Category::Backups => format!("directories with >= {} home markers ({})",
+                settings.home_marker_threshold, settings.home_markers.join(" ")),
+            Category::Applications => "macOS .app bundles and .AppImage files".to_string(),
+            Category::Others => "everything else".to_string(),
+            _ => settings.category_exts.get(cat.as_dir()).map(|e| e.join(" ")).unwrap_or_default(),
+        };
+        println!("{:<10} -> {}/", cat.as_dir(), dest);
+        println!("           {}", matches);
+    }
+    for (pattern, folder) in &settings.category_rules {
+        println!("RULE {} -> {}/", pattern, folder);
+    }
+}
+
+fn run_empty_quarantine(dir: &Path, days: u64, apply: bool) -> Result<ExitCode> {
+    println!("# organizer empty-quarantine: {} (older than {} days)", readable_display(dir), days);
+    let expired = organizer::quarantine::expired(dir, days)?;
+    let actions: Vec<Action> = expired.into_iter()
+        .map(|p| Action::Delete(p, "quarantine expired".to_string()))
+        .collect();
+    let mut engine = ActionEngine::new(apply, false, None)?;
+    engine.execute_all(&actions)?;
+    println!("# DONE. {} folder(s), {} {}.", actions.len(), human_size(engine.freed_bytes()),
+        if apply { "freed" } else { "would be freed (dry-run only)" });
+    Ok(if engine.error_count() > 0 { ExitCode::from(EXIT_PARTIAL_FAILURE) } else { ExitCode::SUCCESS })
+}
+
+fn run_verify(cli: &Cli, tree: &Path, plan: bool) -> Result<()> {
+    println!("# organizer verify: {}", readable_display(tree));
+
+    let settings = load_settings(cli)?;
+    let categorizer = Categorizer::new(&settings, cli.use_file_cmd)?;
+
+    let misfiled = verify::find_misfiled(tree, &categorizer)?;
+    for m in &misfiled {
+        println!("MISFILED {} (in {}, rules say {})", readable_display(&m.path), m.found.as_dir(), m.expected.as_dir());
+    }
+
+    if plan && !misfiled.is_empty() {
+        println!("# Fix plan:");
+        let actions: Vec<Action> = misfiled.iter()
+            .map(|m| Action::MoveFile(m.path.clone(), tree.join(settings.dir_name(m.expected.as_dir()))))
+            .collect();
+        let mut engine = ActionEngine::new(false, false, None)?;
+        engine.execute_all(&actions)?;
+    }
 
+    println!("# DONE. {} misfiled file(s).", misfiled.len());
     Ok(())
 }
@@ -0,0 +1,100 @@
+use crate::actions::Action;
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Name of the list of broken symlinks kept in each dated quarantine folder.
+const LINKS_FILE: &str = "broken-symlinks.txt";
+
+/// Turns planned deletions into moves into a dated folder (`<dir>/<YYYY-MM-DD>/`), keeping
+/// each path relative to its root so nothing collides. Broken symlinks have nothing worth
+/// keeping but their target, so they are listed in `broken-symlinks.txt` and still deleted.
+#[derive(Debug)]
+pub struct Quarantine {
+    dir: PathBuf,
+    roots: Vec<PathBuf>,
+    links: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Quarantine {
+    pub fn new(base: &Path, roots: &[PathBuf]) -> Self {
+        Self { dir: base.join(date_name(0)), roots: roots.to_vec(), links: Vec::new() }
+    }
+
+    /// The dated folder this run moves into.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Rewrite a deletion into a move; other actions pass through unchanged.
+    pub fn rewrite(&mut self, action: Action) -> Action {
+        let Action::Delete(path, reason) = action else { return action };
+        let Ok(md) = fs::symlink_metadata(&path) else { return Action::Delete(path, reason) };
+        if md.file_type().is_symlink() {
+            if let Ok(target) = fs::read_link(&path) {
+                self.links.push((path.clone(), target));
+            }
+            return Action::Delete(path, reason);
+        }
+        // Deepest root, so a root nested in another keeps its own layout
+        let rel = self.roots.iter()
+            .filter(|r| path.starts_with(r))
+            .max_by_key(|r| r.components().count())
+            .and_then(|r| path.strip_prefix(r).ok())
+            .unwrap_or(path.file_name().map(Path::new).unwrap_or(&path));
+        let dest = self.dir.join(rel);
+        if md.is_dir() {
+            Action::MoveDir(path, dest)
+        } else {
+            Action::MoveFile(path, dest.parent().unwrap_or(&self.dir).to_path_buf())
+        }
+    }
+
+    /// Whether `action` moves something into the quarantine folder.
+    pub fn contains(&self, action: &Action) -> bool {
+        match action {
+            Action::MoveFile(_, dest) | Action::MoveDir(_, dest) => dest.starts_with(&self.dir),
+            Action::Delete(..) => false,
+        }
+    }
+
+    /// Append the broken symlinks seen so far to the dated folder's list (`link -> target`).
+    pub fn save_links(&mut self) -> Result<()> {
+        if self.links.is_empty() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir).with_context(|| format!("create {}", self.dir.display()))?;
+        let path = self.dir.join(LINKS_FILE);
+        let mut out = fs::OpenOptions::new().create(true).append(true).open(&path)
+            .with_context(|| format!("open {}", path.display()))?;
+        for (link, target) in self.links.drain(..) {
+            writeln!(out, "{} -> {}", link.display(), target.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Dated folders under `base` quarantined more than `days` days ago, oldest first.
+/// Anything not named like a quarantine date is left alone.
+pub fn expired(base: &Path, days: u64) -> Result<Vec<PathBuf>> {
+    let cutoff = date_name(days);
+    let mut out = vec![];
+    for entry in fs::read_dir(base).with_context(|| format!("read {}", base.display()))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // `YYYY-MM-DD` sorts like the date it names
+        if entry.file_type()?.is_dir() && crate::is_date_name(&name) && name < cutoff {
+            out.push(entry.path());
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// `YYYY-MM-DD` of the UTC date `days_ago` days before today.
+fn date_name(days_ago: u64) -> String {
+    let fmt = time::macros::format_description!("[year]-[month]-[day]");
+    let date = time::OffsetDateTime::now_utc() - time::Duration::days(days_ago as i64);
+    date.format(fmt).unwrap_or_default()
+}
@@ -0,0 +1,115 @@
+use crate::actions::Action;
+use crate::dedupe::{DupEntry, DupGroup};
+use crate::utils::readable_display;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A dry-run plan written by `--plan-out` and executed later with `--plan-in`.
+/// Plain JSON, so it can be reviewed (and trimmed) in an editor in between.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedPlan {
+    pub actions: Vec<SavedAction>,
+    /// Duplicate groups, with paths as they will be after the moves above
+    #[serde(default)]
+    pub duplicates: Vec<DupGroup>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedAction {
+    pub action: Action,
+    /// Source size when planned (files only); re-checked before executing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+impl SavedPlan {
+    pub fn new(actions: &[Action], duplicates: Vec<DupGroup>) -> Self {
+        let saved = actions.iter().map(|a| SavedAction {
+            size: match a {
+                Action::MoveFile(src, _) | Action::Delete(src, _) => fs::symlink_metadata(src).ok().map(|m| m.len()),
+                Action::MoveDir(..) => None,
+            },
+            action: a.clone(),
+        }).collect();
+        // Duplicates were found before the moves ran: point them at where the files will be
+        let duplicates = duplicates.into_iter().map(|g| DupGroup {
+            keep: after_moves(actions, &g.keep),
+            size: g.size,
+            duplicates: g.duplicates.into_iter()
+                .map(|d| DupEntry { path: after_moves(actions, &d.path), size: d.size })
+                .collect(),
+        }).collect();
+        Self { actions: saved, duplicates }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("read plan {}", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("parse plan {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?).with_context(|| format!("write plan {}", path.display()))
+    }
+
+    /// Actions whose source is still there with the planned size; the rest are skipped with a warning.
+    pub fn validated_actions(&self) -> Vec<Action> {
+        self.actions.iter().filter_map(|s| {
+            let src = match &s.action {
+                Action::MoveFile(src, _) | Action::MoveDir(src, _) | Action::Delete(src, _) => src,
+            };
+            if unchanged(src, s.size, None) {
+                Some(s.action.clone())
+            } else {
+                println!("WARN: skipping planned action for {} (missing or changed since planning)", readable_display(src));
+                None
+            }
+        }).collect()
+    }
+
+    /// Duplicate groups reduced to members that still match; groups left without a duplicate are dropped.
+    pub fn validated_duplicates(&self) -> Vec<DupGroup> {
+        self.duplicates.iter().filter_map(|g| {
+            if !unchanged(&g.keep, Some(g.size), Some("kept copy")) {
+                return None;
+            }
+            let duplicates: Vec<DupEntry> = g.duplicates.iter()
+                .filter(|d| unchanged(&d.path, Some(d.size), Some("duplicate")))
+                .cloned()
+                .collect();
+            (!duplicates.is_empty()).then(|| DupGroup { keep: g.keep.clone(), size: g.size, duplicates })
+        }).collect()
+    }
+}
+
+/// Whether `path` exists (as itself, not following symlinks) with `size` bytes, when given.
+fn unchanged(path: &Path, size: Option<u64>, what: Option<&str>) -> bool {
+    let ok = match (fs::symlink_metadata(path), size) {
+        (Ok(md), Some(size)) => md.is_dir() || md.len() == size,
+        (Ok(_), None) => true,
+        (Err(_), _) => false,
+    };
+    if let (false, Some(what)) = (ok, what) {
+        println!("WARN: skipping {} {} (missing or changed since planning)", what, readable_display(path));
+    }
+    ok
+}
+
+/// Where `path` ends up once the planned moves ran (collision suffixes aside).
+fn after_moves(actions: &[Action], path: &Path) -> PathBuf {
+    for a in actions {
+        match a {
+            Action::MoveFile(src, dest_dir) if src == path => {
+                return dest_dir.join(src.file_name().unwrap_or_default());
+            }
+            Action::MoveDir(src, dest) => {
+                if let Ok(rel) = path.strip_prefix(src) {
+                    return dest.join(rel);
+                }
+            }
+            _ => {}
+        }
+    }
+    path.to_path_buf()
+}
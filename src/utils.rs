@@ -1,19 +1,481 @@
 use globset::GlobSet;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
 
 pub fn is_broken_symlink(path: &Path) -> bool {
     if let Ok(md) = std::fs::symlink_metadata(path) {
-        if md.file_type().is_symlink() {
-            return std::fs::read_link(path).map(|target| !target.exists()).unwrap_or(true);
+        if md.file_type().is_symlink() || is_reparse_point(&md) {
+            // metadata() follows the link (relative targets resolve against the link's directory)
+            return std::fs::metadata(path).is_err();
         }
     }
     false
 }
 
+/// Windows junctions and other reparse points are not always reported as symlinks.
+#[cfg(windows)]
+fn is_reparse_point(md: &std::fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+    md.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT != 0
+}
+
+#[cfg(not(windows))]
+fn is_reparse_point(_md: &std::fs::Metadata) -> bool {
+    false
+}
+
+/// Device the entry lives on, to tell mount points apart (Unix only).
+#[cfg(unix)]
+pub fn device_id(md: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(md.dev())
+}
+
+#[cfg(not(unix))]
+pub fn device_id(_md: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// (device, inode) where the platform has them; equal ids are hardlinks of one file.
+#[cfg(unix)]
+pub fn file_id(md: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((md.dev(), md.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn file_id(_md: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Whether `path`'s extension passes `--only-ext`/`--ignore-ext`: listed in `only` (when
+/// that isn't empty) and not in `ignore`. Compared case-insensitively, a leading dot
+/// in the lists is ignored; a file without an extension only passes an empty `only`.
+pub fn extension_allowed(path: &Path, only: &[String], ignore: &[String]) -> bool {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let listed = |list: &[String]| list.iter().any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(ext));
+    (only.is_empty() || (!ext.is_empty() && listed(only))) && (ext.is_empty() || !listed(ignore))
+}
+
+/// Whether `a` and `b` name the same place once their parent directories are resolved
+/// (symlinks, `..`). The names themselves are compared as they are, so a symlink and its
+/// target stay two places.
+pub fn same_location(a: &Path, b: &Path) -> bool {
+    if a == b {
+        return true;
+    }
+    let resolve = |p: &Path| {
+        let parent = p.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        Some(std::fs::canonicalize(parent).ok()?.join(p.file_name()?))
+    };
+    matches!((resolve(a), resolve(b)), (Some(x), Some(y)) if x == y)
+}
+
+/// Modification time in nanoseconds since the epoch (0 when unknown), for "unchanged" checks.
+pub fn mtime_ns(md: &std::fs::Metadata) -> u64 {
+    md.modified().ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// A file as it was when recorded: path, size and mtime. Entries of the journal and the
+/// hash cache are only trusted while the file still matches.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct FileStamp {
+    pub path: PathBuf,
+    pub size: u64,
+    pub mtime_ns: u64,
+}
+
+impl FileStamp {
+    pub fn of(path: &Path, md: &std::fs::Metadata) -> Self {
+        Self { path: path.to_path_buf(), size: md.len(), mtime_ns: mtime_ns(md) }
+    }
+
+    /// Still on disk, unchanged.
+    pub fn is_current(&self) -> bool {
+        std::fs::symlink_metadata(&self.path).is_ok_and(|md| FileStamp::of(&self.path, &md) == *self)
+    }
+}
+
+/// Read a JSON-lines file of `what` entries (blank lines skipped); a missing file is empty.
+pub fn read_jsonl<T: serde::de::DeserializeOwned>(path: &Path, what: &str) -> anyhow::Result<Vec<T>> {
+    use anyhow::Context;
+    use std::io::BufRead;
+    let mut entries = vec![];
+    let Ok(f) = std::fs::File::open(path) else { return Ok(entries) };
+    for (i, line) in std::io::BufReader::new(f).lines().enumerate() {
+        let line = line.with_context(|| format!("read {what} {}", path.display()))?;
+        if line.trim().is_empty() { continue; }
+        entries.push(serde_json::from_str(&line).with_context(|| format!("{}:{}: invalid {what} entry", path.display(), i + 1))?);
+    }
+    Ok(entries)
+}
+
+/// Replace `path` with `entries` as JSON lines, written to a temporary file first so a
+/// crash never leaves half a file behind.
+pub fn write_jsonl<T: serde::Serialize>(path: &Path, what: &str, entries: impl IntoIterator<Item = T>) -> anyhow::Result<()> {
+    use anyhow::Context;
+    use std::io::Write;
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+    }
+    let tmp = path.with_extension("tmp");
+    let mut f = std::io::BufWriter::new(std::fs::File::create(&tmp).with_context(|| format!("write {what} {}", tmp.display()))?);
+    for e in entries {
+        writeln!(f, "{}", serde_json::to_string(&e)?)?;
+    }
+    f.into_inner()?.sync_all()?;
+    std::fs::rename(&tmp, path).with_context(|| format!("replace {what} {}", path.display()))
+}
+
+/// Whether other hardlinks share this file's data (Unix only).
+#[cfg(unix)]
+pub fn has_other_links(md: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    md.nlink() > 1
+}
+
+#[cfg(not(unix))]
+pub fn has_other_links(_md: &std::fs::Metadata) -> bool {
+    false
+}
+
+/// Which size of a file counts for size limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SizeMetric {
+    /// The length programs see
+    #[default]
+    Logical,
+    /// Blocks actually allocated on disk, so sparse files count for what they occupy (Unix only)
+    Allocated,
+}
+
+impl SizeMetric {
+    pub fn of(self, md: &std::fs::Metadata) -> u64 {
+        match self {
+            SizeMetric::Logical => md.len(),
+            SizeMetric::Allocated => allocated_size(md),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn allocated_size(md: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    // `st_blocks` is always in 512-byte units
+    md.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn allocated_size(md: &std::fs::Metadata) -> u64 {
+    md.len()
+}
+
+/// Create a symlink at `link` pointing to `target` (file or directory).
+#[cfg(unix)]
+pub fn make_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Create a symlink at `link` pointing to `target` (file or directory).
+#[cfg(windows)]
+pub fn make_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    const ERROR_PRIVILEGE_NOT_HELD: i32 = 1314;
+    let res = if target.is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    };
+    res.map_err(|err| match err.raw_os_error() {
+        Some(ERROR_PRIVILEGE_NOT_HELD) => std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "creating symlinks requires administrator rights or Windows Developer Mode",
+        ),
+        _ => err,
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn make_symlink(_target: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "symlinks are not supported on this platform"))
+}
+
+/// Regular files currently held open by any process we can inspect (`/proc/*/fd`).
+/// Processes owned by other users are silently skipped when we lack permission.
+#[cfg(target_os = "linux")]
+pub fn open_files() -> std::collections::HashSet<PathBuf> {
+    let mut open = std::collections::HashSet::new();
+    let Ok(procs) = std::fs::read_dir("/proc") else { return open };
+    for proc in procs.flatten() {
+        if !proc.file_name().as_encoded_bytes().iter().all(u8::is_ascii_digit) {
+            continue;
+        }
+        let Ok(fds) = std::fs::read_dir(proc.path().join("fd")) else { continue };
+        for fd in fds.flatten() {
+            if let Ok(target) = std::fs::read_link(fd.path()) {
+                if target.is_absolute() {
+                    open.insert(target);
+                }
+            }
+        }
+    }
+    open
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn open_files() -> std::collections::HashSet<PathBuf> {
+    std::collections::HashSet::new()
+}
+
+/// Shared rate limit for file I/O: readers sleep once they get ahead of `bytes_per_sec`.
+/// Idle time only earns up to one second of burst.
+#[derive(Debug)]
+pub struct Throttle {
+    bytes_per_sec: u64,
+    window: std::sync::Mutex<(std::time::Instant, u64)>, // window start, bytes since
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self { bytes_per_sec: bytes_per_sec.max(1), window: std::sync::Mutex::new((std::time::Instant::now(), 0)) }
+    }
+
+    /// Account for `bytes` just transferred, sleeping if over budget.
+    pub fn consume(&self, bytes: u64) {
+        let wait = {
+            let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+            let elapsed = window.0.elapsed();
+            let due = std::time::Duration::from_secs_f64(window.1 as f64 / self.bytes_per_sec as f64);
+            if elapsed > due + std::time::Duration::from_secs(1) {
+                *window = (std::time::Instant::now(), 0);
+            }
+            window.1 += bytes;
+            std::time::Duration::from_secs_f64(window.1 as f64 / self.bytes_per_sec as f64).saturating_sub(window.0.elapsed())
+        };
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// `Read` adapter charging every read against an optional `Throttle`.
+pub struct ThrottledRead<'a, R> {
+    inner: R,
+    throttle: Option<&'a Throttle>,
+}
+
+impl<'a, R: std::io::Read> ThrottledRead<'a, R> {
+    pub fn new(inner: R, throttle: Option<&'a Throttle>) -> Self {
+        Self { inner, throttle }
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for ThrottledRead<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(t) = self.throttle {
+            t.consume(n as u64);
+        }
+        Ok(n)
+    }
+}
+
+/// blake3 of a file's full contents, read through the optional throttle.
+pub fn hash_file(path: &Path, throttle: Option<&Throttle>) -> std::io::Result<[u8; 32]> {
+    let mut hasher = blake3::Hasher::new();
+    std::io::copy(&mut ThrottledRead::new(std::fs::File::open(path)?, throttle), &mut hasher)?;
+    Ok(*hasher.finalize().as_bytes())
+}
+
+/// Like `hash_file`, but through a memory map hashed on all cores; for large files.
+pub fn hash_file_mmap(path: &Path) -> std::io::Result<[u8; 32]> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_mmap_rayon(path)?;
+    Ok(*hasher.finalize().as_bytes())
+}
+
+/// `fs::copy`, chunked through the throttle when one is set (permissions are copied too).
+pub fn copy_file(src: &Path, dst: &Path, throttle: Option<&Throttle>) -> std::io::Result<u64> {
+    let Some(throttle) = throttle else { return std::fs::copy(src, dst) };
+    let input = std::fs::File::open(src)?;
+    let perms = input.metadata()?.permissions();
+    let mut output = std::fs::File::create(dst)?;
+    let n = std::io::copy(&mut ThrottledRead::new(input, Some(throttle)), &mut output)?;
+    output.set_permissions(perms)?;
+    Ok(n)
+}
+
+/// Optional extras for `normalize_file_name` beyond whitespace/extension cleanup.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NameNormalization {
+    pub underscores: bool,
+    pub ascii: bool,
+}
+
+/// Clean up a file name: collapse whitespace runs, trim, lowercase the extension and
+/// optionally replace spaces with `_` or transliterate to ASCII. Non-UTF-8 names are kept.
+pub fn normalize_file_name(name: &std::ffi::OsStr, opts: NameNormalization) -> std::ffi::OsString {
+    let Some(name) = name.to_str() else { return name.to_os_string() };
+    let path = Path::new(name);
+    let (stem, ext) = match (path.file_stem().and_then(|s| s.to_str()), path.extension().and_then(|e| e.to_str())) {
+        (Some(stem), Some(ext)) if !stem.trim().is_empty() => (stem, Some(ext.trim().to_lowercase())),
+        _ => (name, None),
+    };
+    let mut stem = stem.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut ext = ext.unwrap_or_default();
+    if opts.ascii {
+        stem = deunicode::deunicode(&stem);
+        ext = deunicode::deunicode(&ext);
+    }
+    if opts.underscores {
+        stem = stem.replace(' ', "_");
+        ext = ext.replace(' ', "_");
+    }
+    if stem.is_empty() {
+        return name.into();
+    }
+    if ext.is_empty() { stem.into() } else { format!("{stem}.{ext}").into() }
+}
+
+/// Paths of a list file: one per line, or NUL-separated when the list holds any NUL byte
+/// (`find -print0`). Empty entries are dropped; names that aren't UTF-8 survive on Unix.
+pub fn parse_path_list(bytes: &[u8]) -> Vec<PathBuf> {
+    let sep = if bytes.contains(&0) { b'\0' } else { b'\n' };
+    bytes.split(|&b| b == sep)
+        .map(|entry| if sep == b'\n' { entry.strip_suffix(b"\r").unwrap_or(entry) } else { entry })
+        .filter(|entry| !entry.is_empty())
+        .map(path_from_bytes)
+        .collect()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Single-character folder a name is filed under with `--alpha-buckets`: its first
+/// character uppercased (`A`–`Z`, `0`–`9`, accents folded: `Émile` → `E`), else `#`.
+pub fn alpha_bucket(name: &std::ffi::OsStr) -> &'static str {
+    const BUCKETS: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let first = name.to_string_lossy().chars().next()
+        .and_then(|c| deunicode::deunicode_char(c)?.chars().next())
+        .map(|c| c.to_ascii_uppercase());
+    match first.and_then(|c| BUCKETS.find(c)) {
+        Some(i) => &BUCKETS[i..i + 1],
+        None => "#",
+    }
+}
+
+/// Last two extension components, lowercased (`archive.tar.gz` → `tar.gz`, `app.min.js` → `min.js`).
+/// `None` unless the name has a non-empty stem before them.
+pub fn compound_extension(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    let mut parts = name.rsplitn(3, '.');
+    let (last, prev, stem) = (parts.next()?, parts.next()?, parts.next()?);
+    if stem.is_empty() || prev.is_empty() || last.is_empty() {
+        return None;
+    }
+    Some(format!("{prev}.{last}").to_lowercase())
+}
+
+/// Dotfile / dot-directory name (`.ssh`, `.bashrc`, ...).
+pub fn is_hidden_name(name: &std::ffi::OsStr) -> bool {
+    name.as_encoded_bytes().first() == Some(&b'.')
+}
+
+/// Test the full path, then the bare file name, so `thumbs.db` works as well as `**/thumbs.db`.
 pub fn is_pattern_match(matcher: &GlobSet, path: &Path) -> bool {
-    matcher.is_match(path)
+    matcher.is_match(path) || path.file_name().is_some_and(|name| matcher.is_match(name))
 }
 
 pub fn readable_display(p: &Path) -> String {
     p.to_string_lossy().into_owned()
 }
+
+/// Modification time of a file as a UTC date.
+pub fn file_mtime_date(path: &Path) -> Option<OffsetDateTime> {
+    let mtime = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(OffsetDateTime::from(mtime))
+}
+
+/// Expand `{year}`, `{month}` and `{day}` in a destination template.
+pub fn expand_date_template(template: &str, date: OffsetDateTime) -> PathBuf {
+    let s = template
+        .replace("{year}", &format!("{:04}", date.year()))
+        .replace("{month}", &format!("{:02}", u8::from(date.month())))
+        .replace("{day}", &format!("{:02}", date.day()));
+    PathBuf::from(s)
+}
+
+/// Parse a human size like `500M`, `10G`, `1.5TiB` or a plain byte count (binary units).
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let t = s.trim();
+    let split = t.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(t.len());
+    let (num, unit) = t.split_at(split);
+    let n: f64 = num.parse().map_err(|_| format!("invalid size: {s}"))?;
+    let mult: u64 = match unit.trim().to_ascii_lowercase().trim_end_matches("ib").trim_end_matches('b') {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        "t" => 1 << 40,
+        _ => return Err(format!("invalid size unit: {s}")),
+    };
+    Ok((n * mult as f64) as u64)
+}
+
+/// Parse a `--throttle` rate in MiB/s (fractions allowed) into bytes per second.
+pub fn parse_rate(s: &str) -> Result<u64, String> {
+    let mib: f64 = s.trim().parse().map_err(|_| format!("invalid rate `{s}` (MiB/s, e.g. 20 or 0.5)"))?;
+    if mib.is_nan() || mib <= 0.0 {
+        return Err(format!("rate must be positive, got `{s}`"));
+    }
+    Ok((mib * 1024.0 * 1024.0) as u64)
+}
+
+/// Parse an age in whole days: `30`, `30d` or `4w`.
+pub fn parse_days(s: &str) -> Result<u64, String> {
+    let t = s.trim().to_ascii_lowercase();
+    let (num, mult) = match t.strip_suffix('w') {
+        Some(num) => (num, 7),
+        None => (t.strip_suffix('d').unwrap_or(&t), 1),
+    };
+    let n: u64 = num.parse().map_err(|_| format!("invalid age `{s}` (days, e.g. 30d or 4w)"))?;
+    Ok(n * mult)
+}
+
+/// Format a byte count with binary units, e.g. `1.5 GiB`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut v = bytes as f64;
+    let mut i = 0;
+    while v >= 1024.0 && i < UNITS.len() - 1 {
+        v /= 1024.0;
+        i += 1;
+    }
+    if i == 0 { format!("{bytes} B") } else { format!("{v:.1} {}", UNITS[i]) }
+}
+
+/// On-disk size of a file, or the recursive size of a directory. Symlinks are not followed.
+pub fn path_size(path: &Path) -> u64 {
+    match std::fs::symlink_metadata(path) {
+        Ok(md) if md.is_dir() => walkdir::WalkDir::new(path).into_iter().flatten()
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.metadata().map(|m| m.len()).unwrap_or(0))
+            .sum(),
+        Ok(md) => md.len(),
+        Err(_) => 0,
+    }
+}
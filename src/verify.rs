@@ -0,0 +1,35 @@
+use crate::categorize::{Categorizer, Category};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct Misfiled {
+    pub path: PathBuf,
+    pub found: Category,
+    pub expected: Category,
+}
+
+/// Walk each category folder under `tree` and re-categorize its files with the current rules.
+/// Whole-directory categories (Projects, GitRepos, Backups, Applications) are not re-checked.
+pub fn find_misfiled(tree: &Path, categorizer: &Categorizer) -> Result<Vec<Misfiled>> {
+    let mut out = vec![];
+    for found in Category::ALL.into_iter().filter(|c| !c.is_whole_dir()) {
+        let dir = tree.join(categorizer.settings().dir_name(found.as_dir()));
+        if !dir.is_dir() { continue; }
+        for entry in walkdir::WalkDir::new(&dir).follow_links(false) {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(err) => {
+                    println!("WARN: skipping entry due to error: {err}");
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() { continue; }
+            let expected = categorizer.categorize_file(entry.path())?;
+            if expected != found {
+                out.push(Misfiled { path: entry.path().to_path_buf(), found, expected });
+            }
+        }
+    }
+    Ok(out)
+}
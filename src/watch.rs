@@ -0,0 +1,93 @@
+use crate::{Config, RunSummary};
+use anyhow::Result;
+
+/// Quiet time a path needs before it is touched, so files still being written stay put.
+#[cfg(feature = "watch")]
+const DEBOUNCE: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Keep the roots organized: plan and execute each root entry that changed, after it has been
+/// quiet for a moment, until Ctrl-C. Run a full pass first; only new arrivals are looked at.
+#[cfg(feature = "watch")]
+pub fn watch(config: &Config) -> Result<RunSummary> {
+    use crate::actions::{stop_requested, Action};
+    use crate::quarantine::Quarantine;
+    use notify_debouncer_mini::{new_debouncer, notify::RecursiveMode, DebouncedEventKind};
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+    use std::sync::mpsc::{channel, RecvTimeoutError};
+
+    let started = std::time::Instant::now();
+    let (tx, rx) = channel();
+    let mut debouncer = new_debouncer(DEBOUNCE, tx)?;
+    for root in &config.roots {
+        debouncer.watcher().watch(root, RecursiveMode::Recursive)?;
+    }
+    let categorizer = config.categorizer()?;
+    let skip_dirs = config.skip_dirs();
+    let dest_roots = config.dest_roots();
+    let mut quarantine = config.quarantine.as_deref().map(|dir| Quarantine::new(dir, &config.roots));
+    let mut engine = config.action_engine()?;
+    engine.set_skip_open_files(config.skip_open_files);
+    engine.set_protect_marker(&config.settings.protect_marker);
+    let mut summary = RunSummary { applied: engine.apply_mode(), ..RunSummary::default() };
+    println!("# WATCH {} (Ctrl-C to stop)", config.roots.iter().map(|r| r.display().to_string()).collect::<Vec<_>>().join(", "));
+
+    while !stop_requested() {
+        let events = match rx.recv_timeout(std::time::Duration::from_millis(500)) {
+            Ok(Ok(events)) => events,
+            Ok(Err(err)) => {
+                println!("WARN: watch: {err}");
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        // The root child each event is in; busy ones wait for a later, quiet event
+        let mut busy: HashSet<PathBuf> = HashSet::new();
+        let mut entries: HashSet<PathBuf> = HashSet::new();
+        for event in &events {
+            if skip_dirs.iter().any(|s| event.path.starts_with(s)) { continue; }
+            let Some(entry) = config.roots.iter().find_map(|root| {
+                let first = event.path.strip_prefix(root).ok()?.components().next()?;
+                Some(root.join(first))
+            }) else { continue };
+            // Downloads in progress wait for their final name
+            let incomplete = !config.include_incomplete && event.path.file_name().is_some_and(|n| config.settings.is_incomplete(n));
+            if event.kind == DebouncedEventKind::AnyContinuous || incomplete {
+                busy.insert(entry);
+            } else if entry.symlink_metadata().is_ok() {
+                entries.insert(entry);
+            }
+        }
+        entries.retain(|e| !busy.contains(e));
+        if entries.is_empty() { continue; }
+
+        let actions: Vec<Action> = crate::plan_entries(config, &categorizer, &entries)?.into_iter()
+            .filter(|a| !(config.copy && matches!(a, Action::Delete(..))))
+            .map(|a| match &mut quarantine { Some(q) => q.rewrite(a), None => a })
+            .collect();
+        if let Some(q) = &mut quarantine {
+            if engine.apply_mode() {
+                q.save_links()?;
+            }
+        }
+        for action in actions.iter().filter(|a| !quarantine.as_ref().is_some_and(|q| q.contains(a))) {
+            summary.tally(action, &dest_roots);
+        }
+        summary.planned += actions.len();
+        let done = engine.execute_all(&actions)?;
+        summary.count(done);
+    }
+    summary.cross_device_skipped = engine.cross_device_skips();
+    summary.freed_bytes = engine.freed_bytes();
+    summary.collect_errors(&engine);
+    summary.elapsed_seconds = started.elapsed().as_secs_f64();
+    println!("# WATCH stopped. {} files and {} dirs moved, {} deleted{}.", summary.files_moved, summary.dirs_moved, summary.deleted,
+        if summary.applied { "" } else { " (dry-run only)" });
+    Ok(summary)
+}
+
+#[cfg(not(feature = "watch"))]
+pub fn watch(_config: &Config) -> Result<RunSummary> {
+    anyhow::bail!("--watch needs a build with `--features watch`")
+}
@@ -0,0 +1,40 @@
+//! File names that are not valid UTF-8 must be moved and deduplicated byte-for-byte.
+#![cfg(unix)]
+
+use organizer::{run, Config, DedupeMethod, DedupePlan, Settings};
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+
+#[test]
+fn invalid_utf8_name_is_moved_intact() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = tmp.path();
+    let name = OsStr::from_bytes(b"caf\xe9.txt");
+    fs::write(root.join(name), "latin-1").unwrap();
+    // An existing file forces the collision suffix to be built from the raw name too
+    fs::create_dir(root.join("Documents")).unwrap();
+    fs::write(root.join("Documents").join(name), "already there").unwrap();
+
+    let mut config = Config::new(root, Settings::load_or_default(&root.join("no-config")).unwrap());
+    config.apply = true;
+    run(config).unwrap();
+
+    assert!(!root.join(name).exists());
+    let moved = root.join("Documents").join(OsStr::from_bytes(b"caf\xe9-1.txt"));
+    assert_eq!(fs::read_to_string(moved).unwrap(), "latin-1");
+}
+
+#[test]
+fn name_dedupe_does_not_merge_lossy_equal_names() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = tmp.path();
+    // Both decode lossily to "a\u{FFFD}.bin"
+    fs::write(root.join(OsStr::from_bytes(b"a\xff.bin")), "1").unwrap();
+    fs::write(root.join(OsStr::from_bytes(b"a\xfe.bin")), "2").unwrap();
+
+    let mut dedupe = DedupePlan::new(vec![DedupeMethod::Name]);
+    dedupe.scan(root).unwrap();
+
+    assert!(dedupe.report().is_empty());
+}
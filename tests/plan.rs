@@ -0,0 +1,674 @@
+//! Dry-run planning over a throwaway tree; nothing outside the temp dir is touched.
+
+use organizer::hash_cache::HashCache;
+use organizer::quarantine::Quarantine;
+use organizer::utils::SizeMetric;
+use organizer::{plan, Action, ActionEngine, Categorizer, Config, DedupeDirMode, DedupeMethod, DedupeNameMode, DedupePlan, DirConflict, Settings};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// Temp tree plus a config using the built-in default lists (no user config is read).
+fn fixture() -> (TempDir, Config) {
+    let tmp = tempfile::tempdir().unwrap();
+    let settings = Settings::load_or_default(&tmp.path().join("no-config")).unwrap();
+    let config = Config::new(tmp.path(), settings);
+    (tmp, config)
+}
+
+fn write(root: &Path, rel: &str, contents: &str) -> PathBuf {
+    let path = root.join(rel);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn media_and_documents_are_categorized() {
+    let (tmp, config) = fixture();
+    let root = tmp.path();
+    let photo = write(root, "photo.jpg", "jpeg");
+    let report = write(root, "report.pdf", "pdf");
+
+    let actions = plan(&config).unwrap();
+
+    assert_eq!(actions.len(), 2);
+    assert!(actions.iter().any(|a| matches!(a, Action::MoveFile(src, dest) if *src == photo && *dest == root.join("Media"))));
+    assert!(actions.iter().any(|a| matches!(a, Action::MoveFile(src, dest) if *src == report && *dest == root.join("Documents"))));
+}
+
+#[cfg(unix)]
+#[test]
+fn broken_symlink_is_deleted() {
+    let (tmp, config) = fixture();
+    let link = tmp.path().join("dangling");
+    std::os::unix::fs::symlink(tmp.path().join("missing"), &link).unwrap();
+
+    let actions = plan(&config).unwrap();
+
+    assert!(matches!(actions.as_slice(), [Action::Delete(path, reason)] if *path == link && reason == "broken symlink"));
+}
+
+#[test]
+fn git_working_copy_moves_as_whole_dir() {
+    let (tmp, config) = fixture();
+    let root = tmp.path();
+    write(root, "project/.git/HEAD", "ref: refs/heads/main\n");
+    write(root, "project/notes.txt", "todo");
+
+    let actions = plan(&config).unwrap();
+
+    assert!(matches!(actions.as_slice(),
+        [Action::MoveDir(src, dest)] if *src == root.join("project") && *dest == root.join("Projects").join("project")));
+}
+
+#[test]
+fn junk_pattern_is_deleted() {
+    let (tmp, config) = fixture();
+    let junk = write(tmp.path(), "download.tmp", "partial");
+
+    let actions = plan(&config).unwrap();
+
+    assert!(matches!(actions.as_slice(), [Action::Delete(path, _)] if *path == junk));
+}
+
+#[test]
+fn bare_name_and_full_path_patterns_both_match() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    config.settings.delete_patterns = vec!["thumbs.db".into(), "**/cache/*.bin".into()];
+    let thumbs = write(root, "photos/thumbs.db", "x");
+    let cached = write(root, "app/cache/blob.bin", "x");
+    write(root, "app/blob.bin", "x");
+
+    let mut deleted: Vec<PathBuf> = plan(&config).unwrap().into_iter()
+        .filter_map(|a| match a { Action::Delete(path, _) => Some(path), _ => None })
+        .collect();
+    deleted.sort();
+
+    assert_eq!(deleted, vec![cached, thumbs]);
+}
+
+#[test]
+fn downloads_in_progress_are_left_alone() {
+    let (tmp, mut config) = fixture();
+    write(tmp.path(), "movie.mkv.part", "partial");
+    write(tmp.path(), "setup.zip.crdownload", "partial");
+
+    assert!(plan(&config).unwrap().is_empty());
+
+    config.include_incomplete = true;
+    assert_eq!(plan(&config).unwrap().len(), 2);
+}
+
+#[test]
+fn listed_paths_replace_the_walk() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    let photo = write(root, "photo.jpg", "jpeg");
+    write(root, "report.pdf", "pdf");
+    let nested = write(root, "trip/beach.jpg", "jpeg");
+    config.paths = Some(vec![photo.clone(), nested.clone(), root.join("missing.jpg")]);
+
+    let actions = plan(&config).unwrap();
+
+    assert_eq!(actions.len(), 2);
+    assert!(actions.iter().all(|a| matches!(a, Action::MoveFile(src, dest) if (*src == photo || *src == nested) && *dest == root.join("Media"))));
+}
+
+#[test]
+fn name_dedupe_groups_same_names() {
+    let (tmp, _config) = fixture();
+    let root = tmp.path();
+    let a = write(root, "Documents/a/notes.txt", "one");
+    let b = write(root, "Documents/b/notes.txt", "two");
+    write(root, "Documents/other.txt", "three");
+
+    let mut dedupe = DedupePlan::new(vec![DedupeMethod::Name]);
+    dedupe.scan(root).unwrap();
+    let groups = dedupe.report();
+
+    assert_eq!(groups.len(), 1);
+    let mut members: Vec<&PathBuf> = groups[0].duplicates.iter().map(|d| &d.path).collect();
+    members.push(&groups[0].keep);
+    members.sort();
+    assert_eq!(members, [&a, &b]);
+}
+
+#[test]
+fn confirm_with_hash_splits_name_groups_by_contents() {
+    let (tmp, _config) = fixture();
+    let root = tmp.path();
+    write(root, "Documents/a/README.md", "one");
+    write(root, "Documents/b/README.md", "two");
+    let c = write(root, "Documents/c/README.md", "one");
+
+    let mut dedupe = DedupePlan::new(vec![DedupeMethod::Name]);
+    dedupe.set_confirm_with_hash(true);
+    dedupe.scan(root).unwrap();
+    let groups = dedupe.report();
+
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].keep, root.join("Documents/a/README.md"));
+    assert!(matches!(groups[0].duplicates.as_slice(), [d] if d.path == c));
+}
+
+#[test]
+fn name_modes_widen_name_matching() {
+    let (tmp, _config) = fixture();
+    let root = tmp.path();
+    write(root, "Media/a/photo.jpg", "one");
+    write(root, "Media/b/photo.JPG", "two");
+    write(root, "Media/c/photo.jpeg", "three");
+
+    let group_sizes = |mode| {
+        let mut dedupe = DedupePlan::new(vec![DedupeMethod::Name]);
+        dedupe.set_name_mode(mode);
+        dedupe.scan(root).unwrap();
+        dedupe.report().iter().map(|g| g.duplicates.len() + 1).collect::<Vec<_>>()
+    };
+
+    assert!(group_sizes(DedupeNameMode::Exact).is_empty());
+    assert_eq!(group_sizes(DedupeNameMode::CaseInsensitive), [2]);
+    assert_eq!(group_sizes(DedupeNameMode::Stem), [3]);
+}
+
+#[test]
+fn multiple_roots_share_the_under_folder_of_the_first() {
+    let (first, mut config) = fixture();
+    let second = tempfile::tempdir().unwrap();
+    let a = write(first.path(), "a.pdf", "a");
+    let b = write(second.path(), "b.pdf", "b");
+    config.roots.push(second.path().to_path_buf());
+    config.under = Some("Organized".into());
+
+    let actions = plan(&config).unwrap();
+
+    let docs = first.path().join("Organized").join("Documents");
+    assert!(matches!(actions.as_slice(),
+        [Action::MoveFile(sa, da), Action::MoveFile(sb, db)] if *sa == a && *sb == b && *da == docs && *db == docs));
+}
+
+#[test]
+fn saved_plan_skips_sources_changed_since_planning() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    let photo = write(root, "photo.jpg", "jpeg");
+    let report = write(root, "report.pdf", "pdf");
+    let plan_dir = tempfile::tempdir().unwrap();
+    let plan_file = plan_dir.path().join("plan.json");
+
+    config.plan_out = Some(plan_file.clone());
+    organizer::run(config.clone()).unwrap();
+    assert!(photo.exists(), "a dry run with --plan-out must not move anything");
+
+    fs::write(&report, "pdf, edited").unwrap();
+    config.plan_out = None;
+    config.plan_in = Some(plan_file);
+    config.apply = true;
+    organizer::run(config).unwrap();
+
+    assert!(root.join("Media/photo.jpg").exists());
+    assert!(report.exists());
+}
+
+#[test]
+fn compound_extension_wins_over_the_last_component() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    config.settings.category_exts.get_mut("Archives").unwrap().push("tar.zst".into());
+    let backup = write(root, "backup.tar.zst", "zstd");
+
+    let actions = plan(&config).unwrap();
+
+    assert!(matches!(actions.as_slice(), [Action::MoveFile(src, dest)] if *src == backup && *dest == root.join("Archives")));
+}
+
+#[test]
+fn app_bundles_and_appimages_go_to_applications() {
+    let (tmp, config) = fixture();
+    let root = tmp.path();
+    write(root, "Tool.app/Contents/MacOS/tool", "bin");
+    write(root, "Tool.app/Contents/Resources/icon.png", "png");
+    let appimage = write(root, "Editor.AppImage", "elf");
+
+    let actions = plan(&config).unwrap();
+
+    assert_eq!(actions.len(), 2);
+    assert!(actions.iter().any(|a| matches!(a, Action::MoveDir(src, dest)
+        if *src == root.join("Tool.app") && *dest == root.join("Applications").join("Tool.app"))));
+    assert!(actions.iter().any(|a| matches!(a, Action::MoveFile(src, dest) if *src == appimage && *dest == root.join("Applications"))));
+}
+
+#[test]
+fn protect_marker_leaves_subtree_alone() {
+    let (tmp, config) = fixture();
+    let root = tmp.path();
+    write(root, "keep/.noorganize", "");
+    write(root, "keep/photo.jpg", "jpeg");
+    write(root, "keep/Thumbs.db", "junk");
+    let loose = write(root, "loose.jpg", "jpeg");
+
+    let actions = plan(&config).unwrap();
+
+    assert!(matches!(actions.as_slice(), [Action::MoveFile(src, _)] if *src == loose));
+}
+
+#[cfg(unix)]
+#[test]
+fn hardlinks_are_reported_as_already_linked() {
+    let (tmp, _config) = fixture();
+    let root = tmp.path();
+    let a = write(root, "Media/a.jpg", "same");
+    fs::hard_link(&a, root.join("Media/b.jpg")).unwrap();
+
+    let mut dedupe = DedupePlan::new(vec![DedupeMethod::Hash]);
+    dedupe.scan(root).unwrap();
+
+    assert!(dedupe.report().is_empty());
+    assert_eq!(dedupe.already_linked().len(), 1);
+}
+
+#[test]
+fn preserved_categories_keep_the_subpath() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    config.settings.preserve_structure = vec!["Media".into()];
+    let photo = write(root, "Trip/2020/photo.jpg", "jpeg");
+    let report = write(root, "Trip/report.pdf", "pdf");
+
+    let actions = plan(&config).unwrap();
+
+    assert!(actions.iter().any(|a| matches!(a, Action::MoveFile(src, dest) if *src == photo && *dest == root.join("Media/Trip/2020"))));
+    assert!(actions.iter().any(|a| matches!(a, Action::MoveFile(src, dest) if *src == report && *dest == root.join("Documents"))));
+}
+
+#[test]
+fn only_keeps_moves_of_the_chosen_category() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    config.only_categories = vec!["media".into()];
+    let photo = write(root, "photo.jpg", "jpeg");
+    write(root, "report.pdf", "pdf");
+    write(root, "project/.git/HEAD", "ref: refs/heads/main\n");
+    write(root, "project/notes.jpg", "jpeg");
+
+    let actions = plan(&config).unwrap();
+
+    assert!(matches!(actions.as_slice(), [Action::MoveFile(src, _)] if *src == photo));
+}
+
+#[test]
+fn build_dirs_are_deleted_whole_only_on_request() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    write(root, "web/node_modules/left-pad/index.js", "module.exports = 1;");
+    write(root, "web/.venv/bin/python", "#!");
+
+    assert!(plan(&config).unwrap().iter().all(|a| !matches!(a, Action::Delete(..))));
+
+    config.clean_build_dirs = true;
+    let mut deleted: Vec<PathBuf> = plan(&config).unwrap().into_iter()
+        .filter_map(|a| match a { Action::Delete(p, _) => Some(p), _ => None })
+        .collect();
+    deleted.sort();
+    assert_eq!(deleted, [root.join("web/.venv"), root.join("web/node_modules")]);
+}
+
+#[test]
+fn dedupe_order_is_stable_across_runs() {
+    let (tmp, _config) = fixture();
+    let root = tmp.path();
+    for dir in ["c", "a", "b", "d"] {
+        for (name, contents) in [("x.txt", "one"), ("y.txt", "two"), ("z.txt", "three")] {
+            write(root, &format!("Documents/{dir}/{name}"), contents);
+        }
+    }
+
+    let deletes = || {
+        let mut dedupe = DedupePlan::new(vec![DedupeMethod::Hash]);
+        dedupe.scan(root).unwrap();
+        dedupe.duplicate_deletes()
+    };
+    let first = deletes();
+
+    assert_eq!(first.len(), 9);
+    assert_eq!(first, deletes());
+    let mut dedupe = DedupePlan::new(vec![DedupeMethod::Hash]);
+    dedupe.scan(root).unwrap();
+    assert!(dedupe.report().iter().all(|g| g.keep.starts_with(root.join("Documents/a"))));
+}
+
+#[test]
+fn merged_project_dir_drops_identical_files() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    write(root, "Projects/project/notes.txt", "todo");
+    write(root, "project/.git/HEAD", "ref: refs/heads/main\n");
+    write(root, "project/notes.txt", "todo");
+    write(root, "project/plan.txt", "new");
+    config.dir_conflict = DirConflict::Merge;
+    config.apply = true;
+
+    organizer::run(config).unwrap();
+
+    let merged = root.join("Projects/project");
+    assert_eq!(fs::read_to_string(merged.join("plan.txt")).unwrap(), "new");
+    assert!(merged.join(".git/HEAD").exists());
+    assert!(!merged.join("notes-1.txt").exists());
+    assert!(!root.join("Projects/project-1").exists());
+    assert!(!root.join("project").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn read_only_destination_aborts_before_any_move() {
+    use std::os::unix::fs::PermissionsExt;
+    let (tmp, mut config) = fixture();
+    let photo = write(tmp.path(), "photo.jpg", "jpeg");
+    let dest = tempfile::tempdir().unwrap();
+    fs::set_permissions(dest.path(), fs::Permissions::from_mode(0o555)).unwrap();
+    // Root ignores permission bits; nothing to check then
+    if fs::write(dest.path().join("probe"), "").is_ok() {
+        return;
+    }
+    config.under = Some(dest.path().join("Organized"));
+    config.apply = true;
+
+    let err = organizer::run(config).unwrap_err();
+
+    assert!(err.to_string().contains("not writable"), "{err}");
+    assert!(photo.exists());
+    fs::set_permissions(dest.path(), fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[test]
+fn protected_duplicates_are_never_acted_on() {
+    let (tmp, _config) = fixture();
+    let root = tmp.path();
+    write(root, "Originals/a/photo.jpg", "same");
+    write(root, "Originals/b/photo.jpg", "same");
+    let copy = write(root, "Media/photo.jpg", "same");
+
+    let mut dedupe = DedupePlan::new(vec![DedupeMethod::Hash]);
+    dedupe.set_protect(&["Originals/**".into()]).unwrap();
+    dedupe.scan(root).unwrap();
+
+    let deletes = dedupe.duplicate_deletes();
+    assert!(matches!(deletes.as_slice(), [Action::Delete(path, _)] if *path == copy));
+}
+
+#[test]
+fn trust_content_overrides_a_wrong_extension() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    let pic = root.join("pic.txt");
+    fs::write(&pic, b"\xff\xd8\xff\xe0\x00\x10JFIF\x00").unwrap();
+    let notes = write(root, "notes.txt", "plain text");
+    config.trust_content = true;
+
+    let actions = plan(&config).unwrap();
+
+    assert!(actions.iter().any(|a| matches!(a, Action::MoveFile(src, dest) if *src == pic && *dest == root.join("Media"))));
+    assert!(actions.iter().any(|a| matches!(a, Action::MoveFile(src, dest) if *src == notes && *dest == root.join("Documents"))));
+}
+
+#[test]
+fn failed_actions_are_collected_with_their_error() {
+    let tmp = TempDir::new().unwrap();
+    let kept = write(tmp.path(), "kept.txt", "x");
+    let gone = tmp.path().join("gone.txt");
+    let mut engine = ActionEngine::new(true, false, None).unwrap();
+
+    engine.execute_all(&[Action::Delete(kept, "junk".into()), Action::Delete(gone.clone(), "junk".into())]).unwrap();
+
+    assert_eq!(engine.error_count(), 1);
+    assert!(matches!(engine.failures(), [(Action::Delete(path, _), error)] if *path == gone && error.starts_with("deleting")));
+}
+
+#[test]
+fn identical_import_dir_keeps_the_preferred_copy() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    write(root, "Projects/import/.git/HEAD", "ref: refs/heads/main\n");
+    write(root, "Projects/import/notes.txt", "old copy");
+    write(root, "import/.git/HEAD", "ref: refs/heads/main\n");
+    let canonical = write(root, "import/notes.txt", "old copy");
+    let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    fs::File::options().write(true).open(&canonical).unwrap().set_modified(mtime).unwrap();
+    config.dedup_dir_mode = DedupeDirMode::KeepPreferred;
+    config.dedup_keep_prefix = Some(PathBuf::from("import"));
+    config.apply = true;
+
+    organizer::run(config).unwrap();
+
+    let kept = root.join("Projects/import/notes.txt");
+    assert_eq!(fs::metadata(&kept).unwrap().modified().unwrap(), mtime);
+    assert!(!root.join("Projects/import-1").exists());
+    assert!(!root.join("import").exists());
+}
+
+#[test]
+fn protected_import_dir_survives_keep_existing() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    write(root, "Projects/import/.git/HEAD", "ref: refs/heads/main\n");
+    write(root, "Projects/import/notes.txt", "old copy");
+    write(root, "import/.git/HEAD", "ref: refs/heads/main\n");
+    let canonical = write(root, "import/notes.txt", "old copy");
+    let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    fs::File::options().write(true).open(&canonical).unwrap().set_modified(mtime).unwrap();
+    config.dedup_dir_mode = DedupeDirMode::KeepExisting;
+    config.dedup_protect = vec!["import/**".into()];
+    config.apply = true;
+
+    organizer::run(config).unwrap();
+
+    let kept = root.join("Projects/import/notes.txt");
+    assert_eq!(fs::metadata(&kept).unwrap().modified().unwrap(), mtime);
+    assert!(!root.join("import").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn dirs_with_different_symlink_targets_are_not_identical() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    write(root, "Projects/import/.git/HEAD", "ref: refs/heads/main\n");
+    std::os::unix::fs::symlink("/etc", root.join("Projects/import/data")).unwrap();
+    write(root, "import/.git/HEAD", "ref: refs/heads/main\n");
+    std::os::unix::fs::symlink("/usr", root.join("import/data")).unwrap();
+    config.dedup_dir_mode = DedupeDirMode::KeepExisting;
+    config.apply = true;
+
+    organizer::run(config).unwrap();
+
+    assert_eq!(fs::read_link(root.join("Projects/import-1/data")).unwrap(), Path::new("/usr"));
+}
+
+#[test]
+fn failed_deletes_free_nothing() {
+    let tmp = TempDir::new().unwrap();
+    let loose = write(tmp.path(), "loose.txt", "x");
+    let gone = tmp.path().join("gone.txt");
+    let mut engine = ActionEngine::new(true, false, None).unwrap();
+
+    engine.execute_all(&[Action::Delete(loose, "junk".into()), Action::Delete(gone, "junk".into())]).unwrap();
+
+    assert_eq!(engine.freed_bytes(), 1);
+    assert_eq!(engine.error_count(), 1);
+}
+
+#[test]
+fn target_free_keeps_a_copy_when_the_survivor_is_junk() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    write(root, "a.tmp", "same contents");
+    let pdf = write(root, "b.pdf", "same contents");
+    config.target_free = Some(1 << 30);
+    config.dedup = vec![DedupeMethod::Hash];
+    config.apply = true;
+
+    organizer::run(config).unwrap();
+
+    assert!(!root.join("a.tmp").exists());
+    assert!(pdf.is_file());
+}
+
+#[test]
+fn pruned_junk_goes_to_quarantine() {
+    let (tmp, config) = fixture();
+    let root = tmp.path().join("data");
+    write(&root, "old/download.tmp", "partial");
+    let base = tmp.path().join("quarantine");
+    let mut quarantine = Quarantine::new(&base, std::slice::from_ref(&root));
+    let junk = config.settings.delete_matcher().unwrap().0;
+    let mut engine = ActionEngine::new(true, false, None).unwrap();
+
+    engine.prune_empty_dirs(&root, &Default::default(), Some(&junk), Some(&mut quarantine)).unwrap();
+
+    assert!(!root.join("old").exists());
+    assert!(quarantine.dir().join("old/download.tmp").is_file());
+}
+
+#[test]
+fn quarantined_copy_is_never_the_one_kept() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    write(root, "report.pdf", "same contents");
+    write(root, "report.tmp", "same contents");
+    config.quarantine = Some(root.join(".q"));
+    config.dedup = vec![DedupeMethod::Hash];
+    config.apply = true;
+
+    organizer::run(config).unwrap();
+
+    assert!(root.join("Documents/report.pdf").is_file());
+}
+
+#[test]
+fn corrupt_mp4_box_sizes_fall_back_to_mtime() {
+    let (tmp, config) = fixture();
+    // An 8-byte `free` box, then one claiming a 64-bit size that wraps the position
+    let mut data = vec![0, 0, 0, 8, b'f', b'r', b'e', b'e', 0, 0, 0, 1, b'f', b'r', b'e', b'e'];
+    data.extend_from_slice(&(u64::MAX - 7).to_be_bytes());
+    let clip = tmp.path().join("clip.mp4");
+    fs::write(&clip, data).unwrap();
+    let categorizer = Categorizer::new(&config.settings, false).unwrap();
+
+    assert!(categorizer.file_date(&clip).is_some());
+}
+
+#[test]
+fn allocated_size_metric_does_not_group_by_blocks() {
+    let tmp = TempDir::new().unwrap();
+    write(tmp.path(), "notes1.txt", "hi");
+    write(tmp.path(), "notes2.txt", &"x".repeat(1000));
+    let mut dedupe = DedupePlan::new(vec![DedupeMethod::Size]);
+    dedupe.set_size_metric(SizeMetric::Allocated);
+
+    dedupe.scan(tmp.path()).unwrap();
+
+    assert!(dedupe.duplicate_deletes().is_empty());
+}
+
+#[test]
+fn hidden_files_are_left_out_of_dedupe() {
+    let tmp = TempDir::new().unwrap();
+    write(tmp.path(), ".ssh/id_rsa", "secret key");
+    write(tmp.path(), "docs/backup_key.txt", "secret key");
+    let scan = |include_hidden| {
+        let mut dedupe = DedupePlan::new(vec![DedupeMethod::Hash]);
+        dedupe.set_include_hidden(include_hidden);
+        dedupe.scan(tmp.path()).unwrap();
+        dedupe.duplicate_deletes().len()
+    };
+
+    assert_eq!(scan(false), 0);
+    assert_eq!(scan(true), 1);
+}
+
+#[test]
+fn default_settings_do_not_detect_every_dir_as_special() {
+    let tmp = TempDir::new().unwrap();
+    write(tmp.path(), "plain/notes.txt", "text");
+    let settings = Settings::default();
+    let categorizer = Categorizer::new(&settings, false).unwrap();
+
+    assert_eq!(categorizer.detect_special_directory(&tmp.path().join("plain")), None);
+}
+
+#[test]
+fn hash_cache_skips_unchanged_files_on_the_next_scan() {
+    let tmp = TempDir::new().unwrap();
+    let root = tmp.path().join("tree");
+    write(&root, "a/photo.jpg", "same bytes");
+    write(&root, "b/photo.jpg", "same bytes");
+    write(&root, "c/other.jpg", "diff bytes");
+    let cache = tmp.path().join("hash-cache.jsonl");
+    let scan = || {
+        let mut dedupe = DedupePlan::new(vec![DedupeMethod::Hash]);
+        dedupe.set_hash_cache(Some(HashCache::load(&cache).unwrap()));
+        dedupe.scan(&root).unwrap();
+        dedupe.save_hash_cache().unwrap();
+        dedupe
+    };
+
+    let first = scan();
+    let second = scan();
+
+    assert_eq!(first.stats().cached_hashes, 0);
+    assert_eq!(second.stats().cached_hashes, 3);
+    assert_eq!(second.stats().bytes_read, 0);
+    assert_eq!(second.report().len(), 1);
+}
+
+#[test]
+fn rerun_on_an_organized_tree_does_nothing() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    write(root, "report.pdf", "pdf");
+    write(root, "photo.jpg", "jpg");
+    config.apply = true;
+
+    organizer::run(config.clone()).unwrap();
+    assert!(plan(&config).unwrap().is_empty());
+
+    // Even asked directly, a file is not moved onto itself (which would add a suffix)
+    let report = root.join("Documents/report.pdf");
+    let mut engine = ActionEngine::new(true, false, None).unwrap();
+    engine.execute(&Action::MoveFile(report.clone(), root.join("Documents"))).unwrap();
+    assert!(report.exists());
+    assert_eq!(fs::read_dir(root.join("Documents")).unwrap().count(), 1);
+}
+
+#[test]
+fn extension_filters_come_before_categories() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    let photo = write(root, "photo.JPG", "jpg");
+    write(root, "report.pdf", "pdf");
+    write(root, "cache.tmp", "junk");
+    write(root, "project/.git/HEAD", "ref: refs/heads/main\n");
+    write(root, "project/shot.jpg", "jpg");
+    config.only_extensions = vec!["jpg".into()];
+
+    let actions = plan(&config).unwrap();
+
+    assert!(matches!(actions.as_slice(), [Action::MoveFile(src, _)] if *src == photo));
+}
+
+#[test]
+fn max_files_stops_before_anything_moves() {
+    let (tmp, mut config) = fixture();
+    let root = tmp.path();
+    let first = write(root, "a.pdf", "a");
+    write(root, "b.pdf", "b");
+    config.max_files = Some(1);
+    config.apply = true;
+
+    let err = organizer::run(config).unwrap_err();
+
+    assert!(err.to_string().contains("more than 1 files"));
+    assert!(first.exists());
+}